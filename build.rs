@@ -0,0 +1,30 @@
+fn main() {
+    #[cfg(feature = "grpc")]
+    generate_grpc_service();
+}
+
+/// Generates the `KeyValue` service of `grpc.rs` without protoc; the messages are defined by hand there and
+/// `proto/key_value.proto` describes the same service for clients in other languages.
+#[cfg(feature = "grpc")]
+fn generate_grpc_service() {
+    use tonic_build::manual::{Builder, Method, Service};
+
+    let method = |name: &str, route_name: &str, input_type: &str, output_type: &str| Method::builder()
+        .name(name)
+        .route_name(route_name)
+        .input_type(format!("crate::grpc::{}", input_type))
+        .output_type(format!("crate::grpc::{}", output_type))
+        .codec_path("tonic::codec::ProstCodec");
+
+    let service = Service::builder()
+        .name("KeyValue")
+        .package("key_value_db")
+        .method(method("get", "Get", "GetRequest", "GetResponse").build())
+        .method(method("put", "Put", "PutRequest", "PutResponse").build())
+        .method(method("delete", "Delete", "DeleteRequest", "DeleteResponse").build())
+        .method(method("scan", "Scan", "ScanRequest", "Record").server_streaming().build())
+        .method(method("watch", "Watch", "WatchRequest", "Change").server_streaming().build())
+        .build();
+
+    Builder::new().compile(&[service]);
+}
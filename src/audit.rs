@@ -0,0 +1,93 @@
+use std::io::{Result, Error, ErrorKind};
+
+use serde_json::{Value, json};
+
+/// Audit entries share the keyspace with other records; their keys are this prefix followed by the entry's
+/// sequence number as 16 hex digits, so they sort in the order they were written.
+pub const AUDIT_KEY_PREFIX: &str = "audit/";
+
+/// The kind of mutation an `AuditEntry` records.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AuditOp {
+    /// `set`, `insert_new` or `put` stored a record.
+    Set,
+    /// `delete`, `delete_value`, `delete_prefix` or `retain` removed a record.
+    Delete,
+    WriteAt,
+    /// `key` was renamed to `new_key`.
+    Rename,
+    /// `merge` replaced the record with the remote version.
+    Merge,
+}
+
+impl AuditOp {
+    fn name(self) -> &'static str {
+        match self {
+            AuditOp::Set => "set",
+            AuditOp::Delete => "delete",
+            AuditOp::WriteAt => "write_at",
+            AuditOp::Rename => "rename",
+            AuditOp::Merge => "merge",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [AuditOp::Set, AuditOp::Delete, AuditOp::WriteAt, AuditOp::Rename, AuditOp::Merge]
+            .into_iter()
+            .find(|op| op.name() == name)
+    }
+}
+
+/// One mutation recorded with `Options::audit`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AuditEntry {
+    pub sequence: u64,
+    pub millis: u64,
+    /// The actor set with `Database::set_actor` when the mutation was made.
+    pub actor: String,
+    pub op: AuditOp,
+    pub key: String,
+    /// The key after a `Rename`.
+    pub new_key: Option<String>,
+}
+
+impl AuditEntry {
+    pub(crate) fn record_key(&self) -> String {
+        format!("{}{:016x}", AUDIT_KEY_PREFIX, self.sequence)
+    }
+
+    /// The value of the entry's record, a JSON object.
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut object = json!({ "millis": self.millis, "actor": self.actor, "op": self.op.name(), "key": self.key });
+        if let Some(new_key) = &self.new_key {
+            object["new_key"] = Value::String(new_key.clone());
+        }
+
+        object.to_string().into_bytes()
+    }
+
+    pub(crate) fn decode(record_key: &str, value: &[u8]) -> Result<Self> {
+        let invalid = || Error::new(ErrorKind::InvalidData, format!("Invalid audit entry {:?}", record_key));
+        let object: Value = serde_json::from_slice(value).map_err(|_| invalid())?;
+        let string = |name: &str| object.get(name).and_then(Value::as_str).map(str::to_string);
+        Ok(AuditEntry {
+            sequence: parse_sequence(record_key).ok_or_else(invalid)?,
+            millis: object.get("millis").and_then(Value::as_u64).ok_or_else(invalid)?,
+            actor: string("actor").ok_or_else(invalid)?,
+            op: string("op").as_deref().and_then(AuditOp::from_name).ok_or_else(invalid)?,
+            key: string("key").ok_or_else(invalid)?,
+            new_key: string("new_key"),
+        })
+    }
+}
+
+/// The sequence number of the entry with `record_key`, or `None` if it isn't an audit entry's key.
+pub(crate) fn parse_sequence(record_key: &str) -> Option<u64> {
+    u64::from_str_radix(record_key.strip_prefix(AUDIT_KEY_PREFIX)?, 16).ok()
+}
+
+/// State of an open database with `Options::audit`.
+pub(crate) struct AuditLog {
+    pub actor: String,
+    pub next_sequence: u64,
+}
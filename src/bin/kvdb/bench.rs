@@ -0,0 +1,182 @@
+use std::{collections::HashMap, io::{Result, Error, ErrorKind}, str::FromStr, time::{Duration, Instant}};
+
+use key_value_db::{Database, determinism::DeterministicRng};
+
+/// Skew of the zipfian distribution, the YCSB default: a few keys get most of the traffic.
+const ZIPF_EXPONENT: f64 = 0.99;
+
+#[derive(Clone, Copy)]
+pub enum KeyDistribution {
+    Uniform,
+    Zipf,
+}
+
+impl FromStr for KeyDistribution {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "uniform" => Ok(KeyDistribution::Uniform),
+            "zipf" => Ok(KeyDistribution::Zipf),
+            _ => Err(Error::new(ErrorKind::InvalidInput, format!("Unknown key distribution {:?}", s))),
+        }
+    }
+}
+
+pub struct Workload {
+    pub operations: u64,
+    pub keys: u64,
+    /// Share of reads in percent; the rest are updates.
+    pub read_percent: u64,
+    /// Value sizes are uniformly distributed in this range, both ends included.
+    pub min_value_size: usize,
+    pub max_value_size: usize,
+    pub distribution: KeyDistribution,
+    pub seed: u64,
+}
+
+impl Workload {
+    pub fn from_options(options: &HashMap<&str, &str>) -> Result<Self> {
+        let (min_value_size, max_value_size) = match options.get("value-size").copied().unwrap_or("100") {
+            size if size.contains('-') => {
+                let (min, max) = size.split_once('-').unwrap();
+                (parse_number(min, "value-size")?, parse_number(max, "value-size")?)
+            },
+            size => (parse_number(size, "value-size")?, parse_number(size, "value-size")?),
+        };
+
+        let workload = Workload {
+            operations: parse_option(options, "ops", 100_000)?,
+            keys: parse_option(options, "keys", 1_000)?,
+            read_percent: parse_option(options, "read-percent", 90)?,
+            min_value_size,
+            max_value_size,
+            distribution: options.get("distribution").copied().unwrap_or("uniform").parse()?,
+            seed: parse_option(options, "seed", 0)?,
+        };
+
+        if workload.keys == 0 || workload.read_percent > 100 || min_value_size > max_value_size {
+            return Err(Error::new(ErrorKind::InvalidInput,
+                "Expected at least one key, a read percentage up to 100 and value-size MIN-MAX with MIN <= MAX"));
+        }
+
+        Ok(workload)
+    }
+}
+
+fn parse_option<T: FromStr>(options: &HashMap<&str, &str>, name: &str, default: T) -> Result<T> {
+    options.get(name).map_or(Ok(default), |value| parse_number(value, name))
+}
+
+fn parse_number<T: FromStr>(value: &str, name: &str) -> Result<T> {
+    value.parse().map_err(|_| Error::new(ErrorKind::InvalidInput, format!("Invalid --{} {:?}", name, value)))
+}
+
+/// Picks key indexes in `0..keys`; with `Zipf`, index 0 is the hottest key.
+struct KeyChooser {
+    /// Cumulative probabilities of the key indexes, empty for `Uniform`.
+    zipf_cdf: Vec<f64>,
+    keys: u64,
+}
+
+impl KeyChooser {
+    fn new(distribution: KeyDistribution, keys: u64) -> Self {
+        let zipf_cdf = match distribution {
+            KeyDistribution::Uniform => Vec::new(),
+            KeyDistribution::Zipf => {
+                let weights: Vec<f64> = (1..=keys).map(|rank| 1.0 / (rank as f64).powf(ZIPF_EXPONENT)).collect();
+                let total: f64 = weights.iter().sum();
+                weights.iter()
+                    .scan(0.0, |sum, weight| {
+                        *sum += weight / total;
+                        Some(*sum)
+                    })
+                    .collect()
+            },
+        };
+
+        KeyChooser { zipf_cdf, keys }
+    }
+
+    fn next(&self, rng: &mut DeterministicRng) -> u64 {
+        if self.zipf_cdf.is_empty() {
+            return rng.next_below(self.keys);
+        }
+
+        let point = (rng.next_u64() >> 11) as f64 / (1_u64 << 53) as f64;
+        (self.zipf_cdf.partition_point(|&p| p < point) as u64).min(self.keys - 1)
+    }
+}
+
+pub struct Report {
+    pub load_time: Duration,
+    pub run_time: Duration,
+    pub operations: u64,
+    /// Latencies in nanoseconds, sorted.
+    pub read_latencies: Vec<u64>,
+    pub update_latencies: Vec<u64>,
+}
+
+impl Report {
+    pub fn print(&self) {
+        println!("Load: {:.3} s", self.load_time.as_secs_f64());
+        println!("Run: {:.3} s, {:.0} ops/s", self.run_time.as_secs_f64(),
+            self.operations as f64 / self.run_time.as_secs_f64());
+        print_latencies("Reads", &self.read_latencies);
+        print_latencies("Updates", &self.update_latencies);
+    }
+}
+
+fn print_latencies(name: &str, latencies: &[u64]) {
+    if latencies.is_empty() {
+        return;
+    }
+
+    let percentile = |p: f64| latencies[((p / 100.0 * latencies.len() as f64).ceil() as usize).clamp(1, latencies.len()) - 1];
+    let micros = |nanos: u64| nanos as f64 / 1000.0;
+    println!("{}: {:?}, latency us p50 {:.1}, p90 {:.1}, p99 {:.1}, p99.9 {:.1}, max {:.1}", name, latencies.len(),
+        micros(percentile(50.0)), micros(percentile(90.0)), micros(percentile(99.0)), micros(percentile(99.9)),
+        micros(latencies[latencies.len() - 1]));
+}
+
+/// Stores `workload.keys` records, then runs the read/update mix on them. An update replaces the value of a key.
+pub fn run(db: &mut Database, workload: &Workload) -> Result<Report> {
+    let mut rng = DeterministicRng::new(workload.seed);
+    let load_start = Instant::now();
+    for key in 0..workload.keys {
+        db.set(&format!("key{}", key), &random_value(&mut rng, workload))?;
+    }
+    let load_time = load_start.elapsed();
+
+    let chooser = KeyChooser::new(workload.distribution, workload.keys);
+    let mut read_latencies = Vec::new();
+    let mut update_latencies = Vec::new();
+    let mut buffer = Vec::new();
+    let run_start = Instant::now();
+    for _ in 0..workload.operations {
+        let key = format!("key{}", chooser.next(&mut rng));
+        if rng.next_below(100) < workload.read_percent {
+            let start = Instant::now();
+            db.get_into(&key, &mut buffer)?;
+            read_latencies.push(start.elapsed().as_nanos() as u64);
+        }
+        else {
+            let value = random_value(&mut rng, workload);
+            let start = Instant::now();
+            db.delete(&key)?;
+            db.set(&key, &value)?;
+            update_latencies.push(start.elapsed().as_nanos() as u64);
+        }
+    }
+    let run_time = run_start.elapsed();
+
+    read_latencies.sort_unstable();
+    update_latencies.sort_unstable();
+    Ok(Report { load_time, run_time, operations: workload.operations, read_latencies, update_latencies })
+}
+
+fn random_value(rng: &mut DeterministicRng, workload: &Workload) -> Vec<u8> {
+    let size_range = (workload.max_value_size - workload.min_value_size) as u64 + 1;
+    let size = workload.min_value_size + rng.next_below(size_range) as usize;
+    (0..size).map(|i| (i % 251) as u8).collect()
+}
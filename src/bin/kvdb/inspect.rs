@@ -0,0 +1,48 @@
+use std::io::{Write, Result};
+
+use key_value_db::{Database, BlockAddress};
+
+const HEXDUMP_WIDTH: usize = 16;
+
+pub fn print_page(db: &mut Database, index: i32, mut out: impl Write) -> Result<()> {
+    let page = db.inspect_page(index)?;
+    writeln!(out, "Page {:?}", page.index)?;
+    match page.first_free_block {
+        Some(block) => writeln!(out, "First free block: {:?}", block)?,
+        None => writeln!(out, "First free block: none")?,
+    }
+
+    let busy_blocks = page.blocks.iter().filter(|b| b.busy).count();
+    writeln!(out, "Busy blocks: {:?}/{:?}", busy_blocks, page.blocks.len())?;
+
+    for block in &page.blocks {
+        writeln!(out)?;
+        writeln!(out, "Block {:?} ({}, next: {})", block.index, if block.busy { "busy" } else { "free" }, block.next_block)?;
+        for (line_index, line) in block.raw.chunks(HEXDUMP_WIDTH).enumerate() {
+            let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+            let ascii: String = line.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }).collect();
+            writeln!(out, "  {:04x}  {}  |{}|", line_index * HEXDUMP_WIDTH, hex.join(" "), ascii)?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn print_chain(db: &mut Database, key: &str, mut out: impl Write) -> Result<bool> {
+    let Some(chain) = db.record_chain(key)? else {
+        return Ok(false);
+    };
+
+    let pages = {
+        let mut pages: Vec<i32> = chain.iter().map(|a| a.page_index).collect();
+        pages.dedup();
+        pages.len()
+    };
+
+    writeln!(out, "Record {:?}: {:?} blocks, {:?} page switches", key, chain.len(), pages.saturating_sub(1))?;
+    for BlockAddress { page_index, block_index, .. } in chain {
+        writeln!(out, "  P: {:?}, B: {:?}", page_index, block_index)?;
+    }
+
+    Ok(true)
+}
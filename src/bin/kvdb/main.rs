@@ -0,0 +1,178 @@
+use std::{env, process::exit, collections::HashMap, io::{self, Result}};
+
+use key_value_db::{Database, Options};
+use transfer::{TransferOptions, Format, ValueEncoding};
+
+mod bench;
+mod inspect;
+mod transfer;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("bench") => bench(&args[1..]),
+        Some("upgrade") => upgrade(&args[1..]),
+        Some("export") => export(&args[1..]),
+        Some("import") => import(&args[1..]),
+        Some("inspect") => inspect(&args[1..]),
+        Some("stats") => stats(&args[1..]),
+        #[cfg(any(feature = "grpc", feature = "rest"))]
+        Some("serve") => serve(&args[1..]),
+        _ => usage(),
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("Usage:");
+    eprintln!("  kvdb bench [path] [options]       run a read/update workload, on a temporary database by default");
+    eprintln!("  kvdb upgrade <path>               convert a database file to the current format");
+    eprintln!("  kvdb export <path> [options]      write all records to stdout");
+    eprintln!("  kvdb import <path> [options]      read records from stdin");
+    eprintln!("  kvdb inspect page <path> <n>      hexdump page n with decoded block states and next pointers");
+    eprintln!("  kvdb inspect chain <path> <key>   list the blocks occupied by a record");
+    eprintln!("  kvdb stats <path>                 record counts and key size, value size and block count histograms");
+    #[cfg(feature = "grpc")]
+    eprintln!("  kvdb serve <path> --grpc <addr>   serve the KeyValue gRPC service on addr, e.g. 127.0.0.1:50051");
+    #[cfg(feature = "rest")]
+    eprintln!("  kvdb serve <path> --http <addr>   serve the REST endpoints on addr, e.g. 127.0.0.1:8080");
+    eprintln!();
+    eprintln!("Export/import options:");
+    eprintln!("  --format json|csv|dump            JSON lines, CSV with a header row or key<TAB>value lines sorted by key (default: json)");
+    eprintln!("  --encoding base64|hex|utf8        value encoding (default: base64)");
+    eprintln!("  --key-column <name>               key field/column name (default: key)");
+    eprintln!("  --value-column <name>             value field/column name (default: value)");
+    eprintln!();
+    eprintln!("Bench options:");
+    eprintln!("  --ops <n>                         operations after loading the keys (default: 100000)");
+    eprintln!("  --keys <n>                        number of keys (default: 1000)");
+    eprintln!("  --read-percent <n>                reads in percent, the rest updates (default: 90)");
+    eprintln!("  --value-size <n>|<min>-<max>      value sizes, uniformly distributed (default: 100)");
+    eprintln!("  --distribution uniform|zipf       key popularity (default: uniform)");
+    eprintln!("  --seed <n>                        seed of the workload (default: 0)");
+    exit(2);
+}
+
+/// Splits arguments into positional ones and `--name value` options.
+fn parse_args(args: &[String]) -> (Vec<&str>, HashMap<&str, &str>) {
+    let mut positional = Vec::new();
+    let mut options = HashMap::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.strip_prefix("--") {
+            Some(name) => {
+                let Some(value) = iter.next() else { usage() };
+                options.insert(name, value.as_str());
+            },
+            None => positional.push(arg.as_str()),
+        }
+    }
+
+    (positional, options)
+}
+
+fn or_exit<T>(result: Result<T>, action: &str) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("{} failed: {}", action, e);
+        exit(1);
+    })
+}
+
+fn upgrade(args: &[String]) {
+    let [path] = args else { usage() };
+    match Database::upgrade_in_place(path) {
+        Ok(true) => println!("{:?} upgraded to format version {:?}", path, key_value_db::FORMAT_VERSION),
+        Ok(false) => println!("{:?} is already in the current format", path),
+        Err(e) => {
+            eprintln!("Upgrade of {:?} failed: {}", path, e);
+            exit(1);
+        }
+    }
+}
+
+fn transfer_options(options: &HashMap<&str, &str>) -> Result<TransferOptions> {
+    Ok(TransferOptions {
+        format: options.get("format").copied().unwrap_or("json").parse::<Format>()?,
+        encoding: options.get("encoding").copied().unwrap_or("base64").parse::<ValueEncoding>()?,
+        key_column: options.get("key-column").copied().unwrap_or("key").to_string(),
+        value_column: options.get("value-column").copied().unwrap_or("value").to_string(),
+    })
+}
+
+fn export(args: &[String]) {
+    let (positional, options) = parse_args(args);
+    let [path] = positional[..] else { usage() };
+    let options = or_exit(transfer_options(&options), "Export");
+    let mut db = or_exit(Database::new(path), "Opening database");
+    let count = or_exit(transfer::export(&mut db, &options, io::stdout().lock()), "Export");
+    eprintln!("Exported {:?} records", count);
+}
+
+fn import(args: &[String]) {
+    let (positional, options) = parse_args(args);
+    let [path] = positional[..] else { usage() };
+    let options = or_exit(transfer_options(&options), "Import");
+    let mut db = or_exit(Database::new(path), "Opening database");
+    let count = or_exit(transfer::import(&mut db, &options, io::stdin().lock()), "Import");
+    eprintln!("Imported {:?} records", count);
+}
+
+fn inspect(args: &[String]) {
+    let [kind, path, target] = args else { usage() };
+    let mut db = or_exit(Database::new(path), "Opening database");
+    let out = io::stdout().lock();
+    match kind.as_str() {
+        "page" => {
+            let Ok(index) = target.parse() else { usage() };
+            or_exit(inspect::print_page(&mut db, index, out), "Inspect");
+        },
+        "chain" => {
+            if !or_exit(inspect::print_chain(&mut db, target, out), "Inspect") {
+                eprintln!("Key {:?} not found", target);
+                exit(1);
+            }
+        },
+        _ => usage(),
+    }
+}
+
+fn stats(args: &[String]) {
+    let [path] = args else { usage() };
+    let mut db = or_exit(Database::new(path), "Opening database");
+    let stats = or_exit(db.stats(), "Stats");
+    println!("Records: {:?} ({:?} with a shared value)", stats.record_count, stats.shared_value_records);
+    println!("Pages: {:?}, free blocks: {:?}", stats.page_count, stats.free_blocks);
+    print!("Key sizes: {}", stats.key_sizes);
+    print!("Value sizes: {}", stats.value_sizes);
+    print!("Blocks per record: {}", stats.blocks_per_record);
+}
+
+#[cfg(any(feature = "grpc", feature = "rest"))]
+fn serve(args: &[String]) {
+    let (positional, options) = parse_args(args);
+    let [path] = positional[..] else { usage() };
+    let parse_address = |address: &str| -> std::net::SocketAddr {
+        or_exit(address.parse().map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e)), "Parsing address")
+    };
+
+    let runtime = or_exit(tokio::runtime::Runtime::new(), "Starting runtime");
+    let result = match (options.get("grpc"), options.get("http")) {
+        #[cfg(feature = "grpc")]
+        (Some(address), None) => runtime.block_on(key_value_db::grpc::serve(path, parse_address(address))),
+        #[cfg(feature = "rest")]
+        (None, Some(address)) => runtime.block_on(key_value_db::rest::serve(path, parse_address(address))),
+        _ => usage(),
+    };
+    or_exit(result, "Serving");
+}
+
+fn bench(args: &[String]) {
+    let (positional, options) = parse_args(args);
+    let workload = or_exit(bench::Workload::from_options(&options), "Bench");
+    let mut db = match positional[..] {
+        [] => or_exit(Database::temporary(Options::default()), "Creating database"),
+        [path] => or_exit(Database::new(path), "Opening database"),
+        _ => usage(),
+    };
+
+    or_exit(bench::run(&mut db, &workload), "Bench").print();
+}
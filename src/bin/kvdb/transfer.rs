@@ -0,0 +1,216 @@
+use std::{io::{BufRead, Write, Result, Error, ErrorKind}, str::FromStr};
+
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use key_value_db::Database;
+use serde_json::{Map, Value};
+
+#[derive(Clone, Copy)]
+pub enum Format {
+    Json,
+    Csv,
+    /// `key<TAB>value` lines sorted by key and value, so dumps of equal databases are identical and diff well.
+    /// Tabs, line breaks and backslashes in keys are escaped.
+    Dump,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            "dump" => Ok(Format::Dump),
+            _ => Err(Error::new(ErrorKind::InvalidInput, format!("Unknown format {:?}", s))),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum ValueEncoding {
+    Base64,
+    Hex,
+    Utf8,
+}
+
+impl FromStr for ValueEncoding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "base64" => Ok(ValueEncoding::Base64),
+            "hex" => Ok(ValueEncoding::Hex),
+            "utf8" => Ok(ValueEncoding::Utf8),
+            _ => Err(Error::new(ErrorKind::InvalidInput, format!("Unknown encoding {:?}", s))),
+        }
+    }
+}
+
+impl ValueEncoding {
+    fn encode(self, value: &[u8]) -> Result<String> {
+        match self {
+            ValueEncoding::Base64 => Ok(BASE64.encode(value)),
+            ValueEncoding::Hex => Ok(value.iter().map(|b| format!("{:02x}", b)).collect()),
+            ValueEncoding::Utf8 => String::from_utf8(value.to_vec())
+                .map_err(|_| Error::new(ErrorKind::InvalidData, "Value is not valid UTF-8, use base64 or hex encoding")),
+        }
+    }
+
+    fn decode(self, value: &str) -> Result<Vec<u8>> {
+        match self {
+            ValueEncoding::Base64 => BASE64.decode(value).map_err(|e| Error::new(ErrorKind::InvalidData, e)),
+            ValueEncoding::Hex => {
+                if !value.len().is_multiple_of(2) {
+                    return Err(Error::new(ErrorKind::InvalidData, "Hex value has an odd length"));
+                }
+
+                (0..value.len())
+                    .step_by(2)
+                    .map(|i| value.get(i..i + 2)
+                        .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Hex value has an invalid digit")))
+                    .collect()
+            },
+            ValueEncoding::Utf8 => Ok(value.as_bytes().to_vec()),
+        }
+    }
+}
+
+pub struct TransferOptions {
+    pub format: Format,
+    pub encoding: ValueEncoding,
+    pub key_column: String,
+    pub value_column: String,
+}
+
+pub fn export(db: &mut Database, options: &TransferOptions, writer: impl Write) -> Result<usize> {
+    let mut count = 0;
+    match options.format {
+        Format::Json => {
+            let mut writer = writer;
+            for record in db.iter() {
+                let (key, value) = record?;
+                let mut object = Map::new();
+                object.insert(options.key_column.clone(), Value::String(key));
+                object.insert(options.value_column.clone(), Value::String(options.encoding.encode(&value)?));
+                serde_json::to_writer(&mut writer, &object)?;
+                writer.write_all(b"\n")?;
+                count += 1;
+            }
+            writer.flush()?;
+        },
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            writer.write_record([&options.key_column, &options.value_column])?;
+            for record in db.iter() {
+                let (key, value) = record?;
+                writer.write_record([key, options.encoding.encode(&value)?])?;
+                count += 1;
+            }
+            writer.flush()?;
+        },
+        Format::Dump => {
+            let mut records = db.iter().collect::<Result<Vec<_>>>()?;
+            // By value too, so the values of duplicate keys come out in the same order every time.
+            records.sort_unstable();
+            let mut writer = writer;
+            for (key, value) in &records {
+                writeln!(writer, "{}\t{}", escape_dump_key(key), options.encoding.encode(value)?)?;
+            }
+            writer.flush()?;
+            count = records.len();
+        },
+    }
+
+    Ok(count)
+}
+
+pub fn import(db: &mut Database, options: &TransferOptions, reader: impl BufRead) -> Result<usize> {
+    let mut count = 0;
+    match options.format {
+        Format::Json => {
+            for line in reader.lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+
+                let object: Map<String, Value> = serde_json::from_str(&line)?;
+                let key = json_string_field(&object, &options.key_column)?;
+                let value = options.encoding.decode(json_string_field(&object, &options.value_column)?)?;
+                count += db.set(key, &value)? as usize;
+            }
+        },
+        Format::Csv => {
+            let mut reader = csv::Reader::from_reader(reader);
+            let headers = reader.headers()?;
+            let key_index = csv_column_index(headers, &options.key_column)?;
+            let value_index = csv_column_index(headers, &options.value_column)?;
+            for record in reader.records() {
+                let record = record?;
+                let value = options.encoding.decode(&record[value_index])?;
+                count += db.set(&record[key_index], &value)? as usize;
+            }
+        },
+        Format::Dump => {
+            for (line_index, line) in reader.lines().enumerate() {
+                let line = line?;
+                let invalid = || Error::new(ErrorKind::InvalidData,
+                    format!("Line {:?}: expected key<TAB>value", line_index + 1));
+                let (key, value) = line.split_once('\t').ok_or_else(invalid)?;
+                let key = unescape_dump_key(key).ok_or_else(invalid)?;
+                count += db.set(&key, &options.encoding.decode(value)?)? as usize;
+            }
+        },
+    }
+
+    Ok(count)
+}
+
+fn escape_dump_key(key: &str) -> String {
+    let mut escaped = String::with_capacity(key.len());
+    for c in key.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+fn unescape_dump_key(escaped: &str) -> Option<String> {
+    let mut key = String::with_capacity(escaped.len());
+    let mut chars = escaped.chars();
+    while let Some(c) = chars.next() {
+        key.push(match c {
+            '\\' => match chars.next()? {
+                '\\' => '\\',
+                't' => '\t',
+                'n' => '\n',
+                'r' => '\r',
+                _ => return None,
+            },
+            c => c,
+        });
+    }
+
+    Some(key)
+}
+
+fn json_string_field<'a>(object: &'a Map<String, Value>, name: &str) -> Result<&'a str> {
+    object
+        .get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("Expected a string field {:?}", name)))
+}
+
+fn csv_column_index(headers: &csv::StringRecord, name: &str) -> Result<usize> {
+    headers
+        .iter()
+        .position(|h| h == name)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("No column {:?} in the CSV header", name)))
+}
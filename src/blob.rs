@@ -0,0 +1,44 @@
+use std::{io::{Error, ErrorKind}, fmt::{self, Display}, str::FromStr};
+
+use sha2::{Digest, Sha256};
+
+/// Blobs share the keyspace with other records; their keys are this prefix followed by the hex hash.
+pub const BLOB_KEY_PREFIX: &str = "blob/";
+
+/// SHA-256 of a blob's content, which is also its identity.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct BlobHash(pub [u8; 32]);
+
+impl BlobHash {
+    pub fn of(data: &[u8]) -> Self {
+        BlobHash(Sha256::digest(data).into())
+    }
+
+    pub(crate) fn key(&self) -> String {
+        format!("{}{}", BLOB_KEY_PREFIX, self)
+    }
+}
+
+impl Display for BlobHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{:02x}", b))
+    }
+}
+
+impl FromStr for BlobHash {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let invalid = || Error::new(ErrorKind::InvalidInput, format!("Invalid blob hash {:?}", s));
+        if s.len() != 64 {
+            return Err(invalid());
+        }
+
+        let mut hash = [0; 32];
+        for (i, b) in hash.iter_mut().enumerate() {
+            *b = u8::from_str_radix(s.get(i * 2..i * 2 + 2).ok_or_else(invalid)?, 16).map_err(|_| invalid())?;
+        }
+
+        Ok(BlobHash(hash))
+    }
+}
@@ -0,0 +1,79 @@
+use std::cell::{Cell, RefCell};
+
+/// Source of the byte buffers the database allocates per operation: values returned by `get`, `get_all` and
+/// iterators, and temporary copies of keys and values. Buffers handed to the caller come back through
+/// `Database::recycle`, so a reader that recycles its values doesn't allocate once the pool is warm.
+pub trait BufferPool {
+    /// Returns a buffer with a capacity of at least `capacity` bytes. Its contents don't matter.
+    fn take(&self, capacity: usize) -> Vec<u8>;
+    /// Takes back a buffer that is no longer used.
+    fn give_back(&self, buffer: Vec<u8>);
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct BufferPoolStats {
+    pub takes: u64,
+    /// Takes served with a pooled buffer rather than a new allocation.
+    pub hits: u64,
+    /// Buffers given back and dropped because the pool was full.
+    pub dropped: u64,
+    pub pooled_buffers: usize,
+    pub pooled_bytes: usize,
+}
+
+/// A `BufferPool` that keeps up to `max_buffers` given back buffers and hands out the smallest one that fits.
+pub struct VecPool {
+    max_buffers: usize,
+    buffers: RefCell<Vec<Vec<u8>>>,
+    stats: Cell<BufferPoolStats>,
+}
+
+impl VecPool {
+    pub fn new(max_buffers: usize) -> Self {
+        VecPool { max_buffers, buffers: RefCell::new(Vec::new()), stats: Cell::new(BufferPoolStats::default()) }
+    }
+
+    pub fn stats(&self) -> BufferPoolStats {
+        self.stats.get()
+    }
+}
+
+impl BufferPool for VecPool {
+    fn take(&self, capacity: usize) -> Vec<u8> {
+        let mut stats = self.stats.get();
+        stats.takes += 1;
+        let mut buffers = self.buffers.borrow_mut();
+        let fitting = buffers.iter().enumerate()
+            .filter(|(_, buffer)| buffer.capacity() >= capacity)
+            .min_by_key(|(_, buffer)| buffer.capacity())
+            .map(|(index, _)| index);
+        let buffer = match fitting {
+            Some(index) => {
+                let buffer = buffers.swap_remove(index);
+                stats.hits += 1;
+                stats.pooled_buffers -= 1;
+                stats.pooled_bytes -= buffer.capacity();
+                buffer
+            },
+            None => Vec::with_capacity(capacity),
+        };
+
+        self.stats.set(stats);
+        buffer
+    }
+
+    fn give_back(&self, buffer: Vec<u8>) {
+        let mut stats = self.stats.get();
+        let mut buffers = self.buffers.borrow_mut();
+        if buffers.len() < self.max_buffers {
+            stats.pooled_buffers += 1;
+            stats.pooled_bytes += buffer.capacity();
+            buffers.push(buffer);
+        }
+        else {
+            stats.dropped += 1;
+        }
+
+        self.stats.set(stats);
+    }
+}
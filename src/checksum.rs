@@ -0,0 +1,116 @@
+/// CRC-32C (Castagnoli) polynomial, bit-reversed.
+const POLYNOMIAL: u32 = 0x82f6_3b78;
+const TABLE: [u32; 256] = build_table();
+
+const fn build_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ POLYNOMIAL } else { crc >> 1 };
+            bit += 1;
+        }
+
+        table[i] = crc;
+        i += 1;
+    }
+
+    table
+}
+
+/// CRC-32C of `data`, computed with the CRC instructions of SSE 4.2 or ARMv8 when the CPU has them, eight bytes
+/// per instruction, and with a lookup table otherwise. All paths give the same result, so files move freely
+/// between machines.
+pub fn crc32c(data: &[u8]) -> u32 {
+    #[cfg(target_arch = "x86_64")]
+    if std::is_x86_feature_detected!("sse4.2") {
+        // SAFETY: the CPU supports SSE 4.2.
+        return unsafe { crc32c_sse42(data) };
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    if std::arch::is_aarch64_feature_detected!("crc") {
+        // SAFETY: the CPU supports the CRC instructions.
+        return unsafe { crc32c_armv8(data) };
+    }
+
+    crc32c_table(data)
+}
+
+fn crc32c_table(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &b| TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8))
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse4.2")]
+fn crc32c_sse42(data: &[u8]) -> u32 {
+    use std::arch::x86_64::{_mm_crc32_u64, _mm_crc32_u8};
+
+    let mut words = data.chunks_exact(8);
+    let crc = words.by_ref().fold(!0_u64, |crc, word| _mm_crc32_u64(crc, u64::from_le_bytes(word.try_into().unwrap())));
+    !words.remainder().iter().fold(crc as u32, |crc, &b| _mm_crc32_u8(crc, b))
+}
+
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "crc")]
+fn crc32c_armv8(data: &[u8]) -> u32 {
+    use std::arch::aarch64::{__crc32cd, __crc32cb};
+
+    let mut words = data.chunks_exact(8);
+    let crc = words.by_ref().fold(!0_u32, |crc, word| __crc32cd(crc, u64::from_le_bytes(word.try_into().unwrap())));
+    !words.remainder().iter().fold(crc, |crc, &b| __crc32cb(crc, b))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::determinism::DeterministicRng;
+
+    use super::{crc32c, crc32c_table};
+
+    #[test]
+    fn matches_known_value() {
+        assert_eq!(crc32c_table(b"123456789"), 0xe306_9283);
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+        assert_eq!(crc32c(b""), 0);
+    }
+
+    /// Random slices at every alignment and with lengths that leave every possible remainder of 8-byte words.
+    fn random_slices(mut check: impl FnMut(&[u8])) {
+        let mut rng = DeterministicRng::new(1);
+        let buffer: Vec<u8> = (0..4096).map(|_| rng.next_u64() as u8).collect();
+        for _ in 0..2000 {
+            let offset = rng.next_below(16) as usize;
+            let length = rng.next_below(1024) as usize;
+            check(&buffer[offset..offset + length]);
+        }
+    }
+
+    #[test]
+    fn dispatch_matches_table() {
+        random_slices(|data| assert_eq!(crc32c(data), crc32c_table(data), "length {:?}", data.len()));
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn sse42_matches_table() {
+        if !std::is_x86_feature_detected!("sse4.2") {
+            return;
+        }
+
+        // SAFETY: the CPU supports SSE 4.2.
+        random_slices(|data| assert_eq!(unsafe { super::crc32c_sse42(data) }, crc32c_table(data)));
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    #[test]
+    fn armv8_matches_table() {
+        if !std::arch::is_aarch64_feature_detected!("crc") {
+            return;
+        }
+
+        // SAFETY: the CPU supports the CRC instructions.
+        random_slices(|data| assert_eq!(unsafe { super::crc32c_armv8(data) }, crc32c_table(data)));
+    }
+}
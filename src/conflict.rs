@@ -0,0 +1,26 @@
+/// One side of a conflict in `Database::merge`.
+#[derive(Clone, Copy, Debug)]
+pub struct RecordVersion<'a> {
+    pub value: &'a [u8],
+    /// Milliseconds since the Unix epoch, see `RecordMeta::updated_millis`.
+    pub updated_millis: u64,
+}
+
+/// The version `Database::merge` keeps.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Winner {
+    Local,
+    Remote,
+}
+
+/// The default resolver: the later update wins. Equal times are decided by comparing the values, so both replicas
+/// pick the same winner. Records carry no vector clock or hybrid logical clock, only `updated_millis` from each
+/// replica's own clock, so with skewed clocks the later update by timestamp may not be the later one in fact.
+pub fn last_writer_wins(_key: &str, local: &RecordVersion, remote: &RecordVersion) -> Winner {
+    if (remote.updated_millis, remote.value) > (local.updated_millis, local.value) {
+        Winner::Remote
+    }
+    else {
+        Winner::Local
+    }
+}
@@ -0,0 +1,55 @@
+use std::io::{Result, Error, ErrorKind};
+
+use serde_json::{Value, json};
+
+use crate::paging::BlockAddress;
+
+/// A position in insertion order that outlives the database, e.g. for an export job that saves its progress and
+/// resumes after a restart with `Database::iter_from`. It names the last record returned, so records written
+/// after it are returned on resume even if they didn't exist when the cursor was saved.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct RecordCursor {
+    /// `None` before the first record.
+    pub(crate) last: Option<CursorRecord>,
+}
+
+/// The record a cursor is at: its address, and what tells whether the address still holds it.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub(crate) struct CursorRecord {
+    pub address: BlockAddress,
+    pub key: String,
+    pub created_millis: u64,
+}
+
+impl RecordCursor {
+    /// A cursor before the first record.
+    pub fn start() -> Self {
+        RecordCursor::default()
+    }
+
+    /// Encodes the cursor as a string to store, a JSON object.
+    pub fn to_token(&self) -> String {
+        match &self.last {
+            None => json!({}).to_string(),
+            Some(record) => json!({ "page": record.address.page_index, "block": record.address.block_index,
+                "key": record.key, "created_millis": record.created_millis }).to_string(),
+        }
+    }
+
+    pub fn from_token(token: &str) -> Result<Self> {
+        let invalid = || Error::new(ErrorKind::InvalidInput, format!("Invalid cursor token {:?}", token));
+        let object: Value = serde_json::from_str(token).map_err(|_| invalid())?;
+        if object.as_object().is_some_and(|fields| fields.is_empty()) {
+            return Ok(RecordCursor::start());
+        }
+
+        let number = |name: &str| object.get(name).and_then(Value::as_u64).ok_or_else(invalid);
+        let page_index = i32::try_from(number("page")?).map_err(|_| invalid())?;
+        let block_index = u8::try_from(number("block")?).map_err(|_| invalid())?;
+        Ok(RecordCursor { last: Some(CursorRecord {
+            address: BlockAddress::new(page_index, block_index),
+            key: object.get("key").and_then(Value::as_str).ok_or_else(invalid)?.to_string(),
+            created_millis: number("created_millis")?,
+        }) })
+    }
+}
@@ -0,0 +1,110 @@
+use std::{collections::HashMap, io::{Result, Read, Write}, mem::size_of};
+
+use crate::{paging::{BlockAddress, PageManager}, read_write::{PageReader, PageWriter, BLOCK_DATA_SIZE, free_chain},
+    utils::{ReadableWritable, ReadStructure, WriteStructure, ArrayStructReaderWriter}};
+
+/// Values that fit into a single block aren't shared: the reference would cost about as much as the value.
+pub const MIN_SHARED_VALUE_SIZE: usize = BLOCK_DATA_SIZE;
+
+/// Starts the block chain of a value shared by several records.
+#[derive(Clone)]
+#[repr(C)]
+pub struct SharedValueHeader {
+    pub hash: u64,
+    pub ref_count: u32,
+    pub data_size: i32,
+}
+
+impl ReadableWritable for SharedValueHeader {
+    fn read_to_buffer(read_action: impl FnOnce(&mut [u8]) -> Result<Self>) -> Result<Self> {
+        let mut buffer = [0; size_of::<Self>()];
+        read_action(&mut buffer)
+    }
+}
+
+/// FNV-1a, stored on disk, so it must not depend on the std hasher of the build.
+pub fn value_hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+/// In-memory index from value hash to the shared value chains with that hash.
+#[derive(Default)]
+pub struct SharedValues {
+    chains: HashMap<u64, Vec<BlockAddress>>,
+}
+
+impl SharedValues {
+    pub fn register(&mut self, page_manager: &mut PageManager, address: BlockAddress) -> Result<()> {
+        let header = read_header(page_manager, address)?;
+        let chains = self.chains.entry(header.hash).or_default();
+        if !chains.contains(&address) {
+            chains.push(address);
+        }
+
+        Ok(())
+    }
+
+    /// Returns a chain holding exactly `data` with its reference count incremented,
+    /// writing a new chain if there is none yet.
+    pub fn acquire(&mut self, page_manager: &mut PageManager, data: &[u8]) -> Result<BlockAddress> {
+        let hash = value_hash(data);
+        let mut candidate = Vec::new();
+        for &address in self.chains.get(&hash).into_iter().flatten() {
+            let mut reader = PageReader::new(page_manager, address)?;
+            let header = reader.read_structure::<SharedValueHeader>()?;
+            if header.data_size as usize != data.len() {
+                continue;
+            }
+
+            candidate.resize(data.len(), 0);
+            reader.read_exact(&mut candidate)?;
+            if candidate == data {
+                drop(reader);
+                write_header(page_manager, address, &SharedValueHeader { ref_count: header.ref_count + 1, ..header })?;
+                return Ok(address);
+            }
+        }
+
+        let address = {
+            let mut writer = PageWriter::with_capacity(page_manager, SharedValueHeader::size_in_buffer() + data.len())?;
+            writer.write_structure(&SharedValueHeader { hash, ref_count: 1, data_size: data.len() as i32 })?;
+            writer.write_all(data)?;
+            writer.finish()?
+        };
+
+        self.chains.entry(hash).or_default().push(address);
+        Ok(address)
+    }
+}
+
+/// Drops one reference to the shared value chain at `address`, freeing the chain when it was the last one.
+/// Returns `true` if the chain was freed.
+pub fn release(page_manager: &mut PageManager, shared_values: Option<&mut SharedValues>, address: BlockAddress)
+    -> Result<bool> {
+    let header = read_header(page_manager, address)?;
+    if header.ref_count > 1 {
+        write_header(page_manager, address, &SharedValueHeader { ref_count: header.ref_count - 1, ..header })?;
+        return Ok(false);
+    }
+
+    if let Some(chains) = shared_values.and_then(|s| s.chains.get_mut(&header.hash)) {
+        chains.retain(|&a| a != address);
+    }
+
+    free_chain(page_manager, address)?;
+    Ok(true)
+}
+
+fn read_header(page_manager: &mut PageManager, address: BlockAddress) -> Result<SharedValueHeader> {
+    let page = page_manager.get_page(address.page_index)?;
+    let header = page.get_block_data(address.block_index, 0, SharedValueHeader::size_in_buffer())?.read_structure()?;
+    Ok(header)
+}
+
+fn write_header(page_manager: &mut PageManager, address: BlockAddress, header: &SharedValueHeader) -> Result<()> {
+    let mut buffer = [0; size_of::<SharedValueHeader>()];
+    buffer.write_structure(header)?;
+    let mut page = page_manager.get_page(address.page_index)?;
+    page.set_block_data(address.block_index, &buffer, 0)?;
+    page.commit()
+}
@@ -0,0 +1,103 @@
+use std::{cell::Cell, collections::hash_map::DefaultHasher, hash::{BuildHasher, Hasher, RandomState}, thread,
+    time::{Duration, SystemTime, UNIX_EPOCH}};
+
+/// Source of time for everything the database timestamps or schedules.
+pub trait Clock {
+    /// Milliseconds since the Unix epoch.
+    fn now_millis(&self) -> u64;
+
+    /// Waits for `duration`, e.g. to keep maintenance I/O within its budget.
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+    }
+}
+
+/// Clock that only moves when told to, so time-dependent behavior is reproducible.
+pub struct ManualClock {
+    now_millis: Cell<u64>,
+}
+
+impl ManualClock {
+    pub fn new(now_millis: u64) -> Self {
+        ManualClock { now_millis: Cell::new(now_millis) }
+    }
+
+    pub fn set(&self, now_millis: u64) {
+        self.now_millis.set(now_millis);
+    }
+
+    pub fn advance(&self, millis: u64) {
+        self.now_millis.set(self.now_millis.get() + millis);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_millis(&self) -> u64 {
+        self.now_millis.get()
+    }
+
+    /// Moves the clock instead of waiting.
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration.as_millis() as u64);
+    }
+}
+
+/// SplitMix64 generator: small, fast and identical on every platform for a given seed.
+#[derive(Clone)]
+pub struct DeterministicRng {
+    state: u64,
+}
+
+impl DeterministicRng {
+    pub fn new(seed: u64) -> Self {
+        DeterministicRng { state: seed }
+    }
+
+    /// Generator seeded from the process-wide random source, for the non-deterministic mode.
+    pub fn from_entropy() -> Self {
+        DeterministicRng::new(RandomState::new().hash_one(0))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniformly distributed value in `0..bound`; `bound` must not be zero.
+    pub fn next_below(&mut self, bound: u64) -> u64 {
+        ((self.next_u64() as u128 * bound as u128) >> 64) as u64
+    }
+}
+
+/// Hash state for internal maps whose keys come from a seed instead of the OS.
+#[derive(Clone)]
+pub struct SeededHashState {
+    seed: u64,
+}
+
+impl SeededHashState {
+    pub fn new(seed: u64) -> Self {
+        SeededHashState { seed }
+    }
+}
+
+impl BuildHasher for SeededHashState {
+    type Hasher = DefaultHasher;
+
+    fn build_hasher(&self) -> DefaultHasher {
+        let mut hasher = DefaultHasher::new();
+        hasher.write_u64(self.seed);
+        hasher
+    }
+}
@@ -0,0 +1,46 @@
+/// Counters per row of the sketch. A power of two, so a hash picks a counter by masking.
+const WIDTH: usize = 4096;
+const ROWS: usize = 4;
+/// Counters saturate here, like the 4-bit counters of TinyLFU.
+const MAX_COUNT: u8 = 15;
+/// Accesses after which all counts are halved, so pages that stopped being used lose their standing.
+const SAMPLE_SIZE: u32 = 10 * WIDTH as u32;
+
+/// Approximate access counts of pages, a count-min sketch as used by TinyLFU: each page bumps one counter per row
+/// and its count is the smallest of them, so collisions can only overestimate it.
+pub struct FrequencySketch {
+    counters: Box<[[u8; WIDTH]; ROWS]>,
+    accesses: u32,
+}
+
+impl FrequencySketch {
+    pub fn new() -> Self {
+        FrequencySketch { counters: Box::new([[0; WIDTH]; ROWS]), accesses: 0 }
+    }
+
+    pub fn record(&mut self, page_index: i32) {
+        for (row, counters) in self.counters.iter_mut().enumerate() {
+            let counter = &mut counters[slot(page_index, row)];
+            *counter = (*counter + 1).min(MAX_COUNT);
+        }
+
+        self.accesses += 1;
+        if self.accesses == SAMPLE_SIZE {
+            self.counters.iter_mut().flatten().for_each(|counter| *counter /= 2);
+            self.accesses = 0;
+        }
+    }
+
+    pub fn frequency(&self, page_index: i32) -> u8 {
+        self.counters.iter().enumerate().map(|(row, counters)| counters[slot(page_index, row)]).min().unwrap()
+    }
+}
+
+/// Counter of `page_index` in `row`, from a SplitMix64 finalizer over the index and the row. Not seeded: page
+/// indexes aren't chosen by whoever could want to make them collide.
+fn slot(page_index: i32, row: usize) -> usize {
+    let mut x = (page_index as u32 as u64) ^ ((row as u64 + 1) << 32);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    (x ^ (x >> 31)) as usize & (WIDTH - 1)
+}
@@ -0,0 +1,164 @@
+use std::{io::{Result, Error}, net::SocketAddr, path::PathBuf, pin::Pin};
+
+use tokio::sync::broadcast;
+use tokio_stream::{Stream, StreamExt, iter, wrappers::BroadcastStream};
+use tonic::{Request, Response, Status, transport::Server};
+
+use crate::worker::Worker;
+
+include!(concat!(env!("OUT_DIR"), "/key_value_db.KeyValue.rs"));
+
+pub use key_value_client::KeyValueClient;
+pub use key_value_server::{KeyValue, KeyValueServer};
+
+/// Changes a slow `Watch` stream can fall behind by before it fails with `DATA_LOSS`.
+const WATCH_BUFFER_SIZE: usize = 1024;
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetRequest {
+    #[prost(string, tag = "1")]
+    pub key: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetResponse {
+    /// Not set if there is no such key.
+    #[prost(bytes = "vec", optional, tag = "1")]
+    pub value: Option<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PutRequest {
+    #[prost(string, tag = "1")]
+    pub key: String,
+    #[prost(bytes = "vec", tag = "2")]
+    pub value: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PutResponse {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DeleteRequest {
+    #[prost(string, tag = "1")]
+    pub key: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DeleteResponse {
+    #[prost(bool, tag = "1")]
+    pub deleted: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ScanRequest {
+    /// Only records whose keys start with the prefix; empty for all records.
+    #[prost(string, tag = "1")]
+    pub prefix: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Record {
+    #[prost(string, tag = "1")]
+    pub key: String,
+    #[prost(bytes = "vec", tag = "2")]
+    pub value: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct WatchRequest {
+    /// Only changes of keys that start with the prefix; empty for all changes.
+    #[prost(string, tag = "1")]
+    pub prefix: String,
+}
+
+/// A `Put` or a `Delete` made through the server.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Change {
+    #[prost(string, tag = "1")]
+    pub key: String,
+    /// The new value, not set if the key was deleted.
+    #[prost(bytes = "vec", optional, tag = "2")]
+    pub value: Option<Vec<u8>>,
+}
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = std::result::Result<T, Status>> + Send>>;
+
+/// `KeyValue` backed by a database owned by a `Worker`. `Put` replaces the value of an existing key.
+/// `Watch` only sees changes made through this service, as nothing else can write the file while it's open.
+pub struct KeyValueService {
+    worker: Worker,
+    changes: broadcast::Sender<Change>,
+}
+
+impl KeyValueService {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        Ok(KeyValueService { worker: Worker::start(path.into())?, changes: broadcast::channel(WATCH_BUFFER_SIZE).0 })
+    }
+}
+
+#[tonic::async_trait]
+impl KeyValue for KeyValueService {
+    async fn get(&self, request: Request<GetRequest>) -> std::result::Result<Response<GetResponse>, Status> {
+        let GetRequest { key } = request.into_inner();
+        let value = self.worker.run(move |db| {
+            let mut value = Vec::new();
+            Ok::<_, Error>(db.get_into(&key, &mut value)?.then_some(value))
+        }).await??;
+        Ok(Response::new(GetResponse { value }))
+    }
+
+    async fn put(&self, request: Request<PutRequest>) -> std::result::Result<Response<PutResponse>, Status> {
+        let PutRequest { key, value } = request.into_inner();
+        let change = Change { key: key.clone(), value: Some(value.clone()) };
+        self.worker.run(move |db| db.put(&key, &value)).await??;
+        let _ = self.changes.send(change);
+        Ok(Response::new(PutResponse {}))
+    }
+
+    async fn delete(&self, request: Request<DeleteRequest>) -> std::result::Result<Response<DeleteResponse>, Status> {
+        let DeleteRequest { key } = request.into_inner();
+        let change = Change { key: key.clone(), value: None };
+        let deleted = self.worker.run(move |db| db.delete(&key)).await??;
+        if deleted {
+            let _ = self.changes.send(change);
+        }
+
+        Ok(Response::new(DeleteResponse { deleted }))
+    }
+
+    type ScanStream = ResponseStream<Record>;
+
+    /// Reads the matching records in one go, so the stream is a consistent view of the database.
+    async fn scan(&self, request: Request<ScanRequest>) -> std::result::Result<Response<Self::ScanStream>, Status> {
+        let ScanRequest { prefix } = request.into_inner();
+        let records = self.worker.run(move |db| {
+            db.iter()
+                .filter(|record| record.as_ref().map_or(true, |(key, _)| key.starts_with(&prefix)))
+                .map(|record| record.map(|(key, value)| Record { key, value }))
+                .collect::<Result<Vec<_>>>()
+        }).await??;
+        Ok(Response::new(Box::pin(iter(records.into_iter().map(Ok)))))
+    }
+
+    type WatchStream = ResponseStream<Change>;
+
+    // The item type of the stream is fixed by tonic.
+    #[allow(clippy::result_large_err)]
+    async fn watch(&self, request: Request<WatchRequest>) -> std::result::Result<Response<Self::WatchStream>, Status> {
+        let WatchRequest { prefix } = request.into_inner();
+        let changes = BroadcastStream::new(self.changes.subscribe())
+            .filter(move |change| change.as_ref().map_or(true, |change| change.key.starts_with(&prefix)))
+            .map(|change| change.map_err(|_| Status::data_loss("The watch fell behind and missed changes")));
+        Ok(Response::new(Box::pin(changes)))
+    }
+}
+
+/// Serves the `KeyValue` service for the database at `path` on `address` until the server fails.
+pub async fn serve(path: impl Into<PathBuf>, address: SocketAddr) -> Result<()> {
+    Server::builder()
+        .add_service(KeyValueServer::new(KeyValueService::open(path)?))
+        .serve(address)
+        .await
+        .map_err(Error::other)
+}
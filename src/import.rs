@@ -0,0 +1,149 @@
+use std::{io::{BufRead, Result, Error, ErrorKind}, ops::ControlFlow, str::from_utf8};
+
+use crate::{Database, Progress, progress};
+
+/// Imports the text output of RocksDB/LevelDB `ldb dump` (or `ldb scan`), with or without `--hex`.
+/// Returns the number of imported records.
+pub fn import_ldb_dump(db: &mut Database, reader: impl BufRead) -> Result<usize> {
+    import_ldb_dump_with_progress(db, reader, progress::ignore)
+}
+
+/// `import_ldb_dump` that reports every imported record to `progress`. When `progress` breaks, the records
+/// imported so far are kept and the call fails with a `Cancelled` error.
+pub fn import_ldb_dump_with_progress(db: &mut Database, reader: impl BufRead,
+    mut progress: impl FnMut(Progress) -> ControlFlow<()>) -> Result<usize> {
+    let mut count = 0;
+    for (line_index, line) in reader.split(b'\n').enumerate() {
+        let mut line = line?;
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+
+        if line.is_empty() || line.starts_with(b"Keys in range:") {
+            continue;
+        }
+
+        let (key, value) = split_once(&line, b" ==> ")
+            .or_else(|| split_once(&line, b" : "))
+            .ok_or_else(|| invalid_line(line_index, "expected `key ==> value`"))?;
+        let key = decode_ldb_field(key).ok_or_else(|| invalid_line(line_index, "invalid hex key"))?;
+        let value = decode_ldb_field(value).ok_or_else(|| invalid_line(line_index, "invalid hex value"))?;
+
+        db.set(utf8_key(&key, line_index)?, &value)?;
+        count += 1;
+        progress::report(&mut progress, count as u64, None)?;
+    }
+
+    Ok(count)
+}
+
+/// Imports the output of LMDB `mdb_dump` in either `bytevalue` or `print` (`-p`) format.
+/// Dumps of several databases (`-a`) are imported into the same keyspace.
+/// Returns the number of imported records.
+pub fn import_mdb_dump(db: &mut Database, reader: impl BufRead) -> Result<usize> {
+    import_mdb_dump_with_progress(db, reader, progress::ignore)
+}
+
+/// `import_mdb_dump` that reports every imported record to `progress`, see `import_ldb_dump_with_progress`.
+pub fn import_mdb_dump_with_progress(db: &mut Database, reader: impl BufRead,
+    mut progress: impl FnMut(Progress) -> ControlFlow<()>) -> Result<usize> {
+    let mut count = 0;
+    let mut in_header = true;
+    let mut printable = false;
+    let mut pending_key: Option<Vec<u8>> = None;
+
+    for (line_index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim_end_matches('\r');
+
+        if in_header {
+            match line.split_once('=') {
+                Some(("format", format)) => printable = format == "print",
+                Some(("HEADER", "END")) => in_header = false,
+                Some(_) => {},
+                None => return Err(invalid_line(line_index, "expected a header line")),
+            }
+            continue;
+        }
+
+        if line == "DATA=END" {
+            if pending_key.is_some() {
+                return Err(invalid_line(line_index, "key without a value"));
+            }
+            in_header = true;
+            continue;
+        }
+
+        let field = line.strip_prefix(' ').ok_or_else(|| invalid_line(line_index, "expected a data line"))?;
+        let bytes = if printable { decode_mdb_printable(field) } else { decode_hex(field) }
+            .ok_or_else(|| invalid_line(line_index, "invalid encoding"))?;
+
+        match pending_key.take() {
+            None => pending_key = Some(bytes),
+            Some(key) => {
+                db.set(utf8_key(&key, line_index)?, &bytes)?;
+                count += 1;
+                progress::report(&mut progress, count as u64, None)?;
+            },
+        }
+    }
+
+    if !in_header {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "Dump ends without DATA=END"));
+    }
+
+    Ok(count)
+}
+
+fn decode_ldb_field(field: &[u8]) -> Option<Vec<u8>> {
+    match field.strip_prefix(b"0x") {
+        Some(hex) => decode_hex(from_utf8(hex).ok()?),
+        None => Some(field.to_vec()),
+    }
+}
+
+fn decode_mdb_printable(field: &str) -> Option<Vec<u8>> {
+    let bytes = field.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] != b'\\' {
+            result.push(bytes[index]);
+            index += 1;
+        }
+        else if bytes.get(index + 1) == Some(&b'\\') {
+            result.push(b'\\');
+            index += 2;
+        }
+        else {
+            result.push(u8::from_str_radix(field.get(index + 1..index + 3)?, 16).ok()?);
+            index += 3;
+        }
+    }
+
+    Some(result)
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn split_once<'a>(line: &'a [u8], separator: &[u8]) -> Option<(&'a [u8], &'a [u8])> {
+    let position = line.windows(separator.len()).position(|w| w == separator)?;
+    Some((&line[..position], &line[position + separator.len()..]))
+}
+
+fn utf8_key(key: &[u8], line_index: usize) -> Result<&str> {
+    from_utf8(key).map_err(|_| invalid_line(line_index, "key is not valid UTF-8"))
+}
+
+fn invalid_line(line_index: usize, message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("Line {:?}: {}", line_index + 1, message))
+}
@@ -0,0 +1,82 @@
+use std::io::{Result, Error, ErrorKind};
+
+use crate::{paging::{PageManager, BlockAddress, INVALID_BLOCK_INDEX, PAGE_BLOCK_COUNT, BLOCK_SIZE},
+    read_write::get_next_block_address};
+
+const KEY_PREVIEW_LENGTH: usize = 32;
+
+/// Physical layout of the whole database, for tests and tools that assert on block placement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebugLayout {
+    pub pages: Vec<PageLayout>,
+    /// Records in chain order.
+    pub records: Vec<RecordLayout>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageLayout {
+    pub index: i32,
+    pub free_blocks: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordLayout {
+    pub address: BlockAddress,
+    /// The key, shortened to `KEY_PREVIEW_LENGTH` characters.
+    pub key_preview: String,
+    pub blocks: Vec<BlockAddress>,
+}
+
+/// Decoded content of a single page, as stored in the page cache.
+#[derive(Debug, Clone)]
+pub struct PageInfo {
+    pub index: i32,
+    pub first_free_block: Option<u8>,
+    pub blocks: Vec<BlockInfo>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockInfo {
+    pub index: u8,
+    pub busy: bool,
+    /// Next block of the chain, decoded from the last bytes of the block.
+    pub next_block: BlockAddress,
+    /// All `BLOCK_SIZE` bytes of the block, including the next block address.
+    pub raw: Vec<u8>,
+}
+
+pub fn inspect_page(page_manager: &mut PageManager, index: i32) -> Result<PageInfo> {
+    if index < 0 || index >= page_manager.page_count()? {
+        return Err(Error::new(ErrorKind::NotFound, format!("Page {:?} doesn't exist", index)));
+    }
+
+    let page = page_manager.get_page(index)?;
+    let blocks = (0..PAGE_BLOCK_COUNT as u8)
+        .map(|block_index| Ok(BlockInfo {
+            index: block_index,
+            busy: page.is_block_busy(block_index),
+            next_block: get_next_block_address(&page, block_index)?,
+            raw: page.get_block_data(block_index, 0, BLOCK_SIZE)?.to_vec(),
+        }))
+        .collect::<Result<_>>()?;
+
+    let first_free_block = Some(page.first_free_block()).filter(|&b| b != INVALID_BLOCK_INDEX);
+    Ok(PageInfo { index, first_free_block, blocks })
+}
+
+pub fn page_layouts(page_manager: &mut PageManager) -> Result<Vec<PageLayout>> {
+    (0..page_manager.page_count()?)
+        .map(|index| {
+            let page = page_manager.get_page(index)?;
+            let free_blocks = (0..PAGE_BLOCK_COUNT as u8).filter(|&b| !page.is_block_busy(b)).collect();
+            Ok(PageLayout { index, free_blocks })
+        })
+        .collect()
+}
+
+pub fn key_preview(key: &str) -> String {
+    match key.char_indices().nth(KEY_PREVIEW_LENGTH) {
+        Some((end, _)) => format!("{}...", &key[..end]),
+        None => key.to_string(),
+    }
+}
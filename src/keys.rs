@@ -0,0 +1,215 @@
+use std::io::{Result, Error, ErrorKind};
+
+// Every part starts with a tag, so parts of different types order by tag and decode unambiguously.
+const TAG_STR: char = 's';
+const TAG_INT: char = 'i';
+const TAG_UINT: char = 'u';
+const TAG_UUID: char = 'x';
+
+// Strings end with '\0'; '\0' and '\u{1}' inside them are escaped so the terminator sorts before any content.
+const STR_END: char = '\0';
+const ESCAPE: char = '\u{1}';
+
+/// One component of a composite key.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum KeyPart {
+    Str(String),
+    Int(i64),
+    UInt(u64),
+    Uuid([u8; 16]),
+}
+
+/// Builds a composite key such as `tenant/timestamp/id` whose string order matches the order of its parts:
+/// numbers compare numerically, strings compare as strings and a shorter tuple sorts before its extensions.
+/// The key of a partial tuple is a prefix of the keys of all tuples starting with it, e.g. for `delete_prefix`.
+#[derive(Clone, Default, Debug)]
+pub struct KeyBuilder {
+    key: String,
+}
+
+impl KeyBuilder {
+    pub fn new() -> Self {
+        KeyBuilder::default()
+    }
+
+    pub fn str(mut self, value: &str) -> Self {
+        self.key.push(TAG_STR);
+        for c in value.chars() {
+            match c {
+                STR_END => self.key.push_str("\u{1}\u{1}"),
+                ESCAPE => self.key.push_str("\u{1}\u{2}"),
+                c => self.key.push(c),
+            }
+        }
+        self.key.push(STR_END);
+        self
+    }
+
+    pub fn int(mut self, value: i64) -> Self {
+        // Flipping the sign bit moves negative numbers below positive ones.
+        self.key.push(TAG_INT);
+        self.push_hex(&((value as u64) ^ (1 << 63)).to_be_bytes());
+        self
+    }
+
+    pub fn uint(mut self, value: u64) -> Self {
+        self.key.push(TAG_UINT);
+        self.push_hex(&value.to_be_bytes());
+        self
+    }
+
+    pub fn uuid(mut self, value: [u8; 16]) -> Self {
+        self.key.push(TAG_UUID);
+        self.push_hex(&value);
+        self
+    }
+
+    pub fn part(self, part: &KeyPart) -> Self {
+        match part {
+            KeyPart::Str(value) => self.str(value),
+            KeyPart::Int(value) => self.int(*value),
+            KeyPart::UInt(value) => self.uint(*value),
+            KeyPart::Uuid(value) => self.uuid(*value),
+        }
+    }
+
+    pub fn build(self) -> String {
+        self.key
+    }
+
+    /// Fixed-width lowercase hex, so numbers compare like their big-endian bytes.
+    fn push_hex(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.key.push_str(&format!("{:02x}", b));
+        }
+    }
+}
+
+pub fn encode(parts: &[KeyPart]) -> String {
+    parts.iter().fold(KeyBuilder::new(), KeyBuilder::part).build()
+}
+
+/// Splits a key built by `KeyBuilder` back into its parts.
+pub fn decode(key: &str) -> Result<Vec<KeyPart>> {
+    let mut parts = Vec::new();
+    let mut chars = key.chars();
+    while let Some(tag) = chars.next() {
+        let part = match tag {
+            TAG_STR => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some(STR_END) => break,
+                        Some(ESCAPE) => match chars.next() {
+                            Some('\u{1}') => value.push(STR_END),
+                            Some('\u{2}') => value.push(ESCAPE),
+                            _ => return Err(invalid_key("invalid escape sequence")),
+                        },
+                        Some(c) => value.push(c),
+                        None => return Err(invalid_key("unterminated string part")),
+                    }
+                }
+                KeyPart::Str(value)
+            },
+            TAG_INT => KeyPart::Int((u64::from_be_bytes(take_hex(&mut chars)?) ^ (1 << 63)) as i64),
+            TAG_UINT => KeyPart::UInt(u64::from_be_bytes(take_hex(&mut chars)?)),
+            TAG_UUID => KeyPart::Uuid(take_hex(&mut chars)?),
+            _ => return Err(invalid_key("unknown part tag")),
+        };
+        parts.push(part);
+    }
+
+    Ok(parts)
+}
+
+fn take_hex<const N: usize>(chars: &mut std::str::Chars) -> Result<[u8; N]> {
+    let mut bytes = [0; N];
+    for b in &mut bytes {
+        let mut digit = || chars.next().and_then(|c| c.to_digit(16)).ok_or_else(|| invalid_key("invalid hex number"));
+        *b = (digit()? << 4 | digit()?) as u8;
+    }
+
+    Ok(bytes)
+}
+
+fn invalid_key(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("Invalid composite key: {}", message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KeyPart, decode, encode};
+
+    fn sample_parts() -> Vec<Vec<KeyPart>> {
+        let strs = ["", "\0", "\u{1}", "\u{1}a", "a", "a\0", "a\0b", "a\u{1}", "ab", "b"];
+        let ints = [i64::MIN, -256, -1, 0, 1, 255, i64::MAX];
+        let mut parts = Vec::new();
+        for s in strs {
+            parts.push(vec![KeyPart::Str(s.to_string())]);
+            parts.push(vec![KeyPart::Str(s.to_string()), KeyPart::Int(-1)]);
+            parts.push(vec![KeyPart::Str(s.to_string()), KeyPart::Str(String::new())]);
+        }
+        for i in ints {
+            parts.push(vec![KeyPart::Int(i)]);
+            parts.push(vec![KeyPart::Int(i), KeyPart::Str("\u{1}".to_string())]);
+        }
+        for u in [0, 1, u64::MAX] {
+            parts.push(vec![KeyPart::UInt(u)]);
+        }
+        parts.push(vec![KeyPart::Uuid([0; 16])]);
+        parts.push(vec![KeyPart::Uuid([0xff; 16])]);
+        parts
+    }
+
+    /// Tuples of the same part types in order: numbers numerically, strings by their chars.
+    fn tuple_cmp(a: &[KeyPart], b: &[KeyPart]) -> Option<std::cmp::Ordering> {
+        for (a, b) in a.iter().zip(b) {
+            let ordering = match (a, b) {
+                (KeyPart::Str(a), KeyPart::Str(b)) => a.cmp(b),
+                (KeyPart::Int(a), KeyPart::Int(b)) => a.cmp(b),
+                (KeyPart::UInt(a), KeyPart::UInt(b)) => a.cmp(b),
+                (KeyPart::Uuid(a), KeyPart::Uuid(b)) => a.cmp(b),
+                _ => return None,
+            };
+            if ordering.is_ne() {
+                return Some(ordering);
+            }
+        }
+
+        Some(a.len().cmp(&b.len()))
+    }
+
+    #[test]
+    fn roundtrips() {
+        for parts in sample_parts() {
+            assert_eq!(decode(&encode(&parts)).unwrap(), parts);
+        }
+    }
+
+    #[test]
+    fn byte_order_matches_tuple_order() {
+        let parts = sample_parts();
+        for a in &parts {
+            for b in &parts {
+                if let Some(ordering) = tuple_cmp(a, b) {
+                    assert_eq!(encode(a).as_bytes().cmp(encode(b).as_bytes()), ordering, "{:?} vs {:?}", a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn partial_tuple_is_prefix() {
+        let parts = [KeyPart::Str("a\u{1}".to_string()), KeyPart::Int(-5), KeyPart::Str(String::new())];
+        for length in 0..parts.len() {
+            assert!(encode(&parts).starts_with(&encode(&parts[..length])));
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_keys() {
+        for key in ["s", "sa", "s\u{1}\u{3}\0", "i00", "iz000000000000000", "?"] {
+            assert!(decode(key).is_err(), "{:?}", key);
+        }
+    }
+}
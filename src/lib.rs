@@ -1,159 +1,2558 @@
-use std::{io::{Result, Read, Write}, fs::{OpenOptions, File}, rc::Rc, cell::RefCell, mem::size_of};
+use std::{io::{Result, Read, Write, Seek, SeekFrom, Error, ErrorKind}, collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque}, fmt::{self, Display}, ops::{ControlFlow, RangeBounds}, fs::{OpenOptions, File, TryLockError}, path::Path, rc::Rc, cell::RefCell, mem::size_of, time::{Duration, Instant}};
 
-use paging::{BlockAddress, PageManager};
-use read_write::{PageReader, PageWriter};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use audit::AuditLog;
+use cursor::CursorRecord;
+use maintenance::MaintenanceLog;
+use dedup::{SharedValues, SharedValueHeader, MIN_SHARED_VALUE_SIZE};
+use determinism::{Clock, SystemClock, ManualClock, DeterministicRng, SeededHashState};
+use quota::Quotas;
+use paging::{PageManager, PAGE_SIZE, PAGE_BLOCK_COUNT, BLOCK_SIZE};
+use storage::SharedStorage;
+use throttle::Throttle;
+use trace::{Tracer, Phase};
+use read_write::{PageReader, PageWriter, block_chain, free_chain};
 use utils::{ReadableWritable, ReadStructure, WriteStructure, WriteStructurePos, ReadStructurePos, ArrayStructReaderWriter};
 
-mod paging;
-mod utils;
-mod read_write;
+pub use audit::{AuditEntry, AuditOp, AUDIT_KEY_PREFIX};
+pub use blob::{BlobHash, BLOB_KEY_PREFIX};
+pub use buffer_pool::{BufferPool, BufferPoolStats, VecPool};
+pub use cursor::RecordCursor;
+pub use conflict::{RecordVersion, Winner, last_writer_wins};
+pub use maintenance::{MaintenanceEvent, MaintenanceOp, MAINTENANCE_KEY_PREFIX};
+pub use inspect::{PageInfo, BlockInfo, DebugLayout, PageLayout, RecordLayout};
+pub use paging::{Allocation, BlockAddress, CachePolicy, Poisoned, is_poisoned};
+pub use progress::{Progress, Cancelled, is_cancelled};
+pub use quota::QuotaUsage;
+pub use stats::{Stats, Histogram, SizeEstimate, DryRunReport};
+pub use trace::{OpTrace, PhaseTime};
+pub use storage::Storage;
+
+mod audit;
+mod blob;
+mod buffer_pool;
+mod checksum;
+mod conflict;
+mod cursor;
+mod dedup;
+mod frequency;
+pub mod determinism;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod import;
+pub mod keys;
+mod inspect;
+mod maintenance;
+mod merkle;
+#[cfg(feature = "model_test")]
+pub mod model_test;
+mod paging;
+mod progress;
+mod quota;
+#[cfg(feature = "python")]
+mod python;
+mod stats;
+mod utils;
+mod read_write;
+#[cfg(feature = "rest")]
+pub mod rest;
+mod storage;
+mod throttle;
+mod trace;
+mod upgrade;
+#[cfg(any(feature = "grpc", feature = "rest"))]
+mod worker;
+
+const FORMAT_MAGIC: [u8; 4] = *b"KVDB";
+pub const FORMAT_VERSION: u32 = 4;
+/// Space reserved at the start of the file for `DbSystemInfo`, so new fields don't move the pages.
+const SYSTEM_INFO_SIZE: u64 = 512;
+/// `clone_to` copies in chunks of this size, so its throttle sleeps in small steps.
+const CLONE_CHUNK_SIZE: usize = 64 * 1024;
+/// `estimate_size` walks the record chain of files with up to this many pages and samples pages of bigger ones.
+const EXACT_SIZE_PAGE_LIMIT: i32 = 1024;
+/// Pages `estimate_size` samples, spread evenly over the file.
+const SIZE_SAMPLE_PAGES: i32 = 256;
+/// Pages a full scan such as `verify` or `gc` may add to the cache before it evicts the ones it loaded, so checking
+/// a huge file doesn't pull all of it into memory.
+const SCAN_CACHE_PAGES: usize = 4096;
+/// Pages whose used blocks `gc` tracks in one pass over the record chain, in a bitmap of 8 bytes per page.
+/// Bigger files take several passes.
+const GC_PARTITION_PAGES: i32 = 1 << 22;
+/// Records a lookup walks between checks of its deadline, so reading the clock doesn't slow down short walks.
+const DEADLINE_CHECK_INTERVAL: u32 = 64;
+/// With `Options::trash`, deleted records are kept under this prefix followed by their key.
+pub const TRASH_KEY_PREFIX: &str = "trash/";
+
+pub struct Database {
+    file: SharedStorage,
+    page_manager: PageManager,
+    system_info: DbSystemInfo,
+    /// `system_info` has changes that aren't written yet. Only `last_record` is left unwritten this way:
+    /// it's a hint the real end of the chain is recovered from on open.
+    system_info_dirty: bool,
+    key_buffer: Vec<u8>,
+    clock: Rc<dyn Clock>,
+    sync_mode: SyncMode,
+    sync_interval: Option<Duration>,
+    last_sync_millis: u64,
+    has_unsynced_writes: bool,
+    shared_values: Option<SharedValues>,
+    maintenance_bytes_per_second: Option<u64>,
+    auth_key: Option<Vec<u8>>,
+    duplicate_keys: bool,
+    audit: Option<AuditLog>,
+    maintenance: Option<MaintenanceLog>,
+    trash: bool,
+    quotas: Quotas,
+    buffer_pool: Option<Rc<dyn BufferPool>>,
+    opened_after_clean_shutdown: bool,
+    /// The file is marked as open and is marked clean again when the database is dropped.
+    marked_in_use: bool,
+    operation_timeout: Option<Duration>,
+    /// Deadline of the running `get_with_deadline`, in clock milliseconds.
+    deadline_millis: Option<u64>,
+    tracer: Tracer,
+}
+
+/// How writes are made durable. There is no write-ahead log, so a write is only crash-safe
+/// once the file has been synced after it.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SyncMode {
+    /// Leave write-back to the OS; a crash can lose recent writes.
+    None,
+    /// `sync_data`: file contents plus the metadata needed to read them back (e.g. the length).
+    Data,
+    /// `sync_all`: file contents and all metadata, e.g. modification times.
+    All,
+}
+
+/// Settings applied when opening a database.
+#[derive(Clone)]
+pub struct Options {
+    /// Seed for every internal random decision (e.g. hash seeds of internal maps), so runs are reproducible.
+    /// `None` seeds them from the OS.
+    pub deterministic_seed: Option<u64>,
+    /// Source of time for everything the database timestamps.
+    pub clock: Rc<dyn Clock>,
+    pub sync_mode: SyncMode,
+    /// Minimum time between automatic syncs. `None` syncs after every write;
+    /// otherwise writes since the last sync can be lost in a crash.
+    pub sync_interval: Option<Duration>,
+    /// Store identical values once and let the records share them through a reference-counted chain.
+    /// Files with shared values stay readable with this disabled, but new values are no longer deduplicated.
+    pub dedup_values: bool,
+    /// I/O budget of `defragment_all`, `gc`, `verify` and `clone_to`, which sleep when they get ahead of it,
+    /// so they leave disk bandwidth to `get` and `set`. `None` runs them at full speed.
+    pub maintenance_bytes_per_second: Option<u64>,
+    /// Key of the HMAC-SHA256 the system info and the record headers are signed with, so a file whose system info,
+    /// record links, sizes, flags or timestamps were modified without the key fails to open. Opening walks the whole
+    /// chain to check it. A file created with a key can only be opened with that key, and a file created without one
+    /// can't be opened with one. Keys, values and page bitmaps aren't covered.
+    pub auth_key: Option<Vec<u8>>,
+    /// Page choice for new records, see `Allocation`.
+    pub allocation: Allocation,
+    /// Let a key have several values, like LMDB's `MDB_DUPSORT`: `set` adds a value instead of keeping the existing
+    /// one, `get_all` and `delete_value` work on single values, and iteration returns every value of a key in
+    /// insertion order. Other operations on a key, e.g. `get` and `rename`, see its first value only.
+    pub duplicate_keys: bool,
+    /// Record every mutation in an append-only log under `AUDIT_KEY_PREFIX`, with the actor set through
+    /// `Database::set_actor`, see `Database::audit_range`. Audit records can't be changed through the database
+    /// while this is on: writes to their keys fail with `ErrorKind::PermissionDenied`, `delete_prefix` and
+    /// `retain` skip them and `clear` fails.
+    pub audit: bool,
+    /// Make `delete` move records under `TRASH_KEY_PREFIX` instead of removing them, with their `updated_millis`
+    /// set to the deletion time, so `Database::restore` can bring them back until `Database::purge_trash` removes
+    /// them for good. Only the latest deletion of a key is kept. Other removals, e.g. `delete_prefix`, and
+    /// deleting a record that is already in the trash remove records right away.
+    pub trash: bool,
+    /// Where values, keys and temporary copies are allocated from, see `BufferPool`. `None` allocates each one.
+    pub buffer_pool: Option<Rc<dyn BufferPool>>,
+    /// Run `Database::verify` when opening a file that wasn't closed cleanly, failing the open if it finds damage.
+    pub verify_after_crash: bool,
+    /// Order in which `Database::shrink_cache` evicts pages, see `CachePolicy`.
+    pub cache_policy: CachePolicy,
+    /// How long looking up a key may walk the record chain before the operation fails with `ErrorKind::TimedOut`,
+    /// e.g. on a very long chain or a stalling disk. Writes look their key up before changing anything, so a timed
+    /// out operation leaves the database as it was. `None` waits as long as it takes.
+    pub operation_timeout: Option<Duration>,
+    /// Record crash recovery, `reopen`, `verify`, `defragment_all`, `gc` and `purge_trash` with their outcome in a
+    /// log under `MAINTENANCE_KEY_PREFIX`, see `Database::maintenance_log`. Events are ordinary records otherwise,
+    /// e.g. `delete_prefix` can remove them.
+    pub maintenance_log: bool,
+}
+
+impl Options {
+    /// Options for fuzzing and property tests: seeded decisions and a clock that starts at 0 and only moves manually.
+    pub fn deterministic(seed: u64) -> Self {
+        Options { deterministic_seed: Some(seed), clock: Rc::new(ManualClock::new(0)), ..Default::default() }
+    }
+
+    /// Options for files that come out byte-identical whenever the same operations run in the same order, e.g. seed
+    /// databases shipped with a release: every timestamp is 0 and pages are picked with `Allocation::LowestFree`.
+    pub fn canonical() -> Self {
+        Options::deterministic(0)
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options { deterministic_seed: None, clock: Rc::new(SystemClock), sync_mode: SyncMode::None, sync_interval: None,
+            dedup_values: false, maintenance_bytes_per_second: None, auth_key: None,
+            allocation: Allocation::LowestFree, duplicate_keys: false, audit: false,
+            trash: false, buffer_pool: None, verify_after_crash: false, cache_policy: CachePolicy::PageOrder,
+            operation_timeout: None, maintenance_log: false }
+    }
+}
+
+impl Database {
+    pub fn new(path: impl AsRef<Path>) -> Result<Self> {
+        Database::with_options(path, Options::default())
+    }
+
+    /// `new` with room for `keys` records whose keys and values take `bytes` in total, see `reserve`.
+    pub fn with_capacity(path: impl AsRef<Path>, keys: u64, bytes: u64) -> Result<Self> {
+        let mut db = Database::new(path)?;
+        db.reserve(keys, bytes)?;
+        Ok(db)
+    }
+
+    /// Opens or creates the database at `path`. Only one `Database` can have a file open at a time,
+    /// in this or any other process; opening a file that is in use fails with `ErrorKind::ResourceBusy`.
+    pub fn with_options(path: impl AsRef<Path>, options: Options) -> Result<Self> {
+        let mut open_options = OpenOptions::new();
+        open_options.create(true).truncate(false).read(true).write(true);
+        #[cfg(windows)]
+        {
+            use std::os::windows::fs::OpenOptionsExt;
+            // FILE_SHARE_READ | FILE_SHARE_DELETE: like on Unix, other processes can read, rename or delete the file,
+            // but not open it for writing.
+            open_options.share_mode(0x1 | 0x4);
+        }
+
+        let file = open_options.open(path);
+        #[cfg(windows)]
+        let file = file.map_err(|e| match e.raw_os_error() {
+            Some(ERROR_SHARING_VIOLATION) => database_in_use(),
+            _ => e,
+        });
+        Database::from_file(file?, options)
+    }
+
+    /// Opens a database backed by an anonymous temporary file, which the OS deletes once the database is dropped,
+    /// even if the process crashes. For scratch data sets bigger than memory.
+    pub fn temporary(options: Options) -> Result<Self> {
+        Database::from_file(tempfile::tempfile()?, options)
+    }
+
+    /// Opens the database in an already opened file, which must be readable and writable.
+    /// The file is locked like in `with_options`.
+    pub fn from_file(file: File, options: Options) -> Result<Self> {
+        lock_exclusive(&file)?;
+        Database::from_storage(file, options)
+    }
+
+    /// Opens the database in any `Storage`, e.g. a `Cursor<Vec<u8>>` for a purely in-memory database.
+    /// Nothing prevents two databases from sharing the underlying storage here, so that is up to the caller.
+    pub fn from_storage(storage: impl Storage + 'static, options: Options) -> Result<Self> {
+        let mut rng = match options.deterministic_seed {
+            Some(seed) => DeterministicRng::new(seed),
+            None => DeterministicRng::from_entropy(),
+        };
+
+        let file: SharedStorage = Rc::new(RefCell::new(Box::new(storage)));
+        let tracer = Tracer::default();
+        let page_manager = PageManager::new(file.clone(), SYSTEM_INFO_SIZE, SeededHashState::new(rng.next_u64()),
+            options.allocation, options.cache_policy, tracer.clone())?;
+        let mut db = Database {
+            file: file.clone(),
+            page_manager,
+            system_info: DbSystemInfo::default(),
+            system_info_dirty: false,
+            key_buffer: vec![0; 32],
+            last_sync_millis: options.clock.now_millis(),
+            clock: options.clock,
+            sync_mode: options.sync_mode,
+            sync_interval: options.sync_interval,
+            has_unsynced_writes: false,
+            shared_values: None,
+            maintenance_bytes_per_second: options.maintenance_bytes_per_second,
+            auth_key: options.auth_key,
+            duplicate_keys: options.duplicate_keys,
+            audit: None,
+            maintenance: None,
+            trash: options.trash,
+            quotas: Quotas::default(),
+            buffer_pool: options.buffer_pool,
+            opened_after_clean_shutdown: false,
+            marked_in_use: false,
+            operation_timeout: options.operation_timeout,
+            deadline_millis: None,
+            tracer,
+        };
+        if file.borrow().size()? == 0 {
+            db.initialize()?;
+        }
+
+        db.read_system_info()?;
+        if db.system_info.magic != FORMAT_MAGIC || db.system_info.format_version != FORMAT_VERSION {
+            return Err(Error::new(ErrorKind::InvalidData, "Unsupported database format, run upgrade first"));
+        }
+
+        db.authenticate_system_info()?;
+
+        // A clean shutdown wrote the real end of the chain, and nothing can have damaged the records since.
+        db.opened_after_clean_shutdown = db.system_info.clean_shutdown != 0;
+        let started_millis = db.clock.now_millis();
+        let mut recovered_records = 0;
+        if !db.opened_after_clean_shutdown {
+            recovered_records = db.recover_last_record()?;
+            if options.verify_after_crash {
+                db.verify()?;
+            }
+        }
+
+        db.authenticate_records()?;
+        db.mark_in_use()?;
+        if options.dedup_values {
+            db.load_shared_values()?;
+        }
+
+        if options.audit {
+            db.open_audit_log()?;
+        }
+
+        if options.maintenance_log {
+            let next_sequence = db.next_sequence(maintenance::parse_sequence)?;
+            db.maintenance = Some(MaintenanceLog { next_sequence });
+            if !db.opened_after_clean_shutdown {
+                db.log_maintenance(MaintenanceOp::Recovery, started_millis, Ok(recovered_records), |&records| (records, 0))?;
+            }
+        }
+
+        Ok(db)
+    }
+
+    /// Converts a database file written by an older version of the crate to the current format.
+    /// Returns `false` if the file is already in the current format.
+    pub fn upgrade_in_place(path: impl AsRef<Path>) -> Result<bool> {
+        upgrade::upgrade_in_place(path.as_ref(), &mut progress::ignore)
+    }
+
+    /// `upgrade_in_place` that reports converted records to `progress`. When `progress` breaks,
+    /// the partial copy is removed and the file is left in its old format.
+    pub fn upgrade_in_place_with_progress(path: impl AsRef<Path>,
+        mut progress: impl FnMut(Progress) -> ControlFlow<()>) -> Result<bool> {
+        upgrade::upgrade_in_place(path.as_ref(), &mut progress)
+    }
+
+    fn initialize(&mut self) -> Result<()> {
+        self.system_info = DbSystemInfo {
+            magic: FORMAT_MAGIC,
+            format_version: FORMAT_VERSION,
+            clean_shutdown: 1,
+            ..Default::default()
+        };
+        self.write_system_info()?;
+        Ok(())
+    }
+
+    /// Makes room for `keys` more records whose keys and values take `bytes` in total, e.g. before a bulk load: the
+    /// file is extended with empty pages in one step instead of a page at a time, and the page cache is sized to
+    /// hold them. The estimate assumes records of average size; free blocks already in the file aren't counted.
+    pub fn reserve(&mut self, keys: u64, bytes: u64) -> Result<()> {
+        if keys == 0 {
+            return Ok(());
+        }
+
+        let record_size = RecordHeader::size_in_buffer() as u64 + bytes.div_ceil(keys);
+        let blocks = block_bytes(record_size as usize) / BLOCK_SIZE as u64 * keys;
+        let page_count = self.page_manager.page_count()? as u64 + blocks.div_ceil(PAGE_BLOCK_COUNT as u64);
+        let page_count = i32::try_from(page_count).map_err(|_| Error::new(ErrorKind::InvalidInput,
+            format!("{:?} records of {:?} bytes don't fit in a database", keys, bytes)))?;
+        self.page_manager.reserve_pages(page_count)
+    }
+
+    /// Stores `data` under `key` unless the key already exists. With `Options::duplicate_keys`, adds `data` to the
+    /// values of `key` unless it's one of them already. If a write fails part way, e.g. with
+    /// `ErrorKind::StorageFull` on a full disk, the blocks taken so far are freed again and the database stays
+    /// as it was before the call. Returns `false` if nothing was stored because the record already exists.
+    pub fn set(&mut self, key: &str, data: &[u8]) -> Result<bool> {
+        self.check_not_audit_key(key)?;
+        let key_bytes = key.as_bytes();
+        let existing = if self.duplicate_keys { self.find_value(key_bytes, data)? } else { self.find(key_bytes)? };
+        if existing.is_some() {
+            return Ok(false);
+        }
+
+        self.insert(key_bytes, data, self.clock.now_millis())?;
+        self.audit(AuditOp::Set, key, None)?;
+        self.sync_if_due()?;
+        Ok(true)
+    }
+
+    /// Stores `data` under `key`, or fails with `ErrorKind::AlreadyExists` if the key exists, for callers that
+    /// must not mistake an existing record for their own.
+    pub fn insert_new(&mut self, key: &str, data: &[u8]) -> Result<()> {
+        self.check_not_audit_key(key)?;
+        let key_bytes = key.as_bytes();
+        if self.find(key_bytes)?.is_some() {
+            return Err(Error::new(ErrorKind::AlreadyExists, "A record with the key already exists"));
+        }
+
+        self.insert(key_bytes, data, self.clock.now_millis())?;
+        self.audit(AuditOp::Set, key, None)?;
+        self.sync_if_due()
+    }
+
+    /// Stores `data` under `key`, replacing its value, or all of them with `Options::duplicate_keys`, like `delete`
+    /// followed by `set` but without losing the old value if storing the new one fails: the new record is written
+    /// first and the old ones are removed, or moved to the trash with `Options::trash`, after it. The record moves to
+    /// the end of the insertion order.
+    pub fn put(&mut self, key: &str, data: &[u8]) -> Result<()> {
+        self.check_not_audit_key(key)?;
+        let key_bytes = key.as_bytes();
+        let now = self.clock.now_millis();
+        match self.find(key_bytes)? {
+            None => self.insert(key_bytes, data, now)?,
+            Some((header, _)) => {
+                self.insert_replacing(key, &header, data, now)?;
+
+                // The new record is the last one of the chain, the old ones come before it.
+                let new_address = self.system_info.last_record;
+                let trash_key = (self.trash && !key.starts_with(TRASH_KEY_PREFIX)).then(|| trash_key(key));
+                let mut first = true;
+                while let Some((header, address)) = self.find(key_bytes)? {
+                    if address == new_address {
+                        break;
+                    }
+
+                    self.discard_record(&header, address, trash_key.as_deref(), first)?;
+                    first = false;
+                }
+            },
+        }
+
+        self.audit(AuditOp::Set, key, None)?;
+        self.sync_if_due()
+    }
+
+    /// Reconciles the local record of `key` with a `remote` version of it from another replica, e.g. one found
+    /// through `range_hashes`. A missing local record takes the remote version; otherwise `resolve` decides, unless
+    /// the values are equal. A remote winner replaces the local record and keeps its `updated_millis`, so replicas
+    /// that merge each other's versions with the same resolver end up equal. Returns the winner.
+    pub fn merge(&mut self, key: &str, remote: &RecordVersion,
+        resolve: impl FnOnce(&str, &RecordVersion, &RecordVersion) -> Winner) -> Result<Winner> {
+        self.check_not_audit_key(key)?;
+        let (winner, local_record) = match self.find(key.as_bytes())? {
+            None => (Winner::Remote, None),
+            Some((header, address)) => {
+                let mut value = vec![0; header.data_size as usize];
+                self.value_reader(&header, address)?.read_exact(&mut value)?;
+                let local = RecordVersion { value: &value, updated_millis: header.updated_millis };
+                let winner = if value == remote.value { Winner::Local } else { resolve(key, &local, remote) };
+                (winner, Some((header, address)))
+            },
+        };
+
+        if winner == Winner::Remote {
+            match local_record {
+                None => self.insert(key.as_bytes(), remote.value, remote.updated_millis)?,
+                Some((header, address)) => self.replace_record(key, &header, address, remote)?,
+            }
+            self.audit(AuditOp::Merge, key, None)?;
+            self.sync_if_due()?;
+        }
+
+        Ok(winner)
+    }
+
+    /// Appends `remote` as the record of `key` and only then removes the local record at `address`, so a failed
+    /// insert leaves the local record in place. The local record's bytes don't count against the quotas while
+    /// inserting, since they're freed once it's replaced.
+    fn replace_record(&mut self, key: &str, header: &RecordHeader, address: BlockAddress, remote: &RecordVersion)
+        -> Result<()> {
+        self.insert_replacing(key, header, remote.value, remote.updated_millis)?;
+
+        // The insert may have linked the new record after the local one.
+        let header = self.read_record_header(address)?;
+        self.remove_record(&header, address)?;
+        self.write_system_info()
+    }
+
+    /// Inserts a record of `key` that replaces the one with `header` once the caller removes it, so the replaced
+    /// record's bytes don't count against the quotas while inserting.
+    fn insert_replacing(&mut self, key: &str, header: &RecordHeader, data: &[u8], now: u64) -> Result<()> {
+        let replaced_bytes = block_bytes(header.record_size());
+        self.quotas.release(key.as_bytes(), replaced_bytes);
+        let inserted = self.insert(key.as_bytes(), data, now);
+        // Charged back either way: `remove_record` releases it again.
+        self.quotas.charge(key.as_bytes(), replaced_bytes);
+        inserted
+    }
+
+    /// Sets the actor recorded with the mutations that follow, e.g. the user or service making them.
+    /// Only used with `Options::audit`; starts out empty.
+    pub fn set_actor(&mut self, actor: impl Into<String>) {
+        if let Some(audit) = &mut self.audit {
+            audit.actor = actor.into();
+        }
+    }
+
+    /// Returns the audit entries made within `millis`, e.g. `..` for all of them, oldest first.
+    /// Entries are found in the file whether or not it's opened with `Options::audit`.
+    pub fn audit_range(&mut self, millis: impl RangeBounds<u64>) -> Result<Vec<AuditEntry>> {
+        let mut entries = Vec::new();
+        for record in self.iter() {
+            let (key, value) = record?;
+            if key.starts_with(AUDIT_KEY_PREFIX) {
+                let entry = AuditEntry::decode(&key, &value)?;
+                if millis.contains(&entry.millis) {
+                    entries.push(entry);
+                }
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Appends an entry for a mutation of `key` to the audit log, if there is one. A mutation stays made when
+    /// this fails, so the error tells the caller that it went unrecorded.
+    fn audit(&mut self, op: AuditOp, key: &str, new_key: Option<&str>) -> Result<()> {
+        let Some(audit) = &mut self.audit else {
+            return Ok(());
+        };
+
+        let entry = AuditEntry { sequence: audit.next_sequence, millis: self.clock.now_millis(),
+            actor: audit.actor.clone(), op, key: key.to_string(), new_key: new_key.map(str::to_string) };
+        audit.next_sequence += 1;
+        self.insert(entry.record_key().as_bytes(), &entry.encode(), entry.millis)
+    }
+
+    fn check_not_audit_key(&self, key: &str) -> Result<()> {
+        if self.audit.is_some() && key.starts_with(AUDIT_KEY_PREFIX) {
+            return Err(Error::new(ErrorKind::PermissionDenied, "Audit records can't be changed"));
+        }
+
+        Ok(())
+    }
+
+    /// Writes a record for a key that doesn't exist yet, created and updated at `now`, freeing the blocks taken
+    /// so far if it fails.
+    fn insert(&mut self, key_bytes: &[u8], data: &[u8], now: u64) -> Result<()> {
+        self.page_manager.start_block_log();
+        let result = match self.acquire_shared_value(data) {
+            Ok(shared_value) => self.append_record(key_bytes, data, shared_value, now).map_err(|e| (e, shared_value)),
+            Err(error) => Err((error, None)),
+        };
+        let taken_blocks = self.page_manager.finish_block_log();
+
+        if let Err((error, shared_value)) = result {
+            self.page_manager.roll_back(&taken_blocks)?;
+            match shared_value {
+                // The value chain was written by this call and is freed with the other blocks.
+                Some(address) if taken_blocks.contains(&address) => self.load_shared_values()?,
+                Some(address) => { dedup::release(&mut self.page_manager, self.shared_values.as_mut(), address)?; },
+                None => {},
+            }
+
+            return Err(error);
+        }
+
+        Ok(())
+    }
+
+    fn acquire_shared_value(&mut self, data: &[u8]) -> Result<Option<BlockAddress>> {
+        match &mut self.shared_values {
+            Some(shared_values) if data.len() >= MIN_SHARED_VALUE_SIZE =>
+                Ok(Some(shared_values.acquire(&mut self.page_manager, data)?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Writes a new record and links it after the last one. `system_info` only changes if this succeeds.
+    fn append_record(&mut self, key_bytes: &[u8], data: &[u8], shared_value: Option<BlockAddress>, now: u64)
+        -> Result<()> {
+        let stored_value_size = if shared_value.is_some() { BlockAddress::size_in_buffer() } else { data.len() };
+        let record_size = RecordHeader::size_in_buffer() + key_bytes.len() + stored_value_size;
+        self.quotas.check(key_bytes, block_bytes(record_size))?;
+        let new_record_address = {
+            let _span = self.tracer.span(Phase::Serialization);
+            let mut page_writer = PageWriter::with_capacity(&mut self.page_manager, record_size)?;
+            page_writer.write_structure(&RecordHeader {
+                next_record: BlockAddress::invalid(),
+                prev_record: self.system_info.last_record,
+                key_size: key_bytes.len() as i32,
+                data_size: data.len() as i32,
+                flags: if shared_value.is_some() { RECORD_FLAG_SHARED_VALUE } else { 0 },
+                created_millis: now,
+                updated_millis: now,
+                checksum: 0,
+            }.sealed(self.auth_key.as_deref()))?;
+
+            page_writer.write_all(key_bytes)?;
+            match shared_value {
+                Some(value_address) => page_writer.write_structure(&value_address)?,
+                None => page_writer.write_all(data)?,
+            }
+            page_writer.finish()?
+        };
+
+        let last_record = self.system_info.last_record;
+        if last_record != BlockAddress::invalid() {
+            let header = self.read_record_header(last_record)?;
+            self.write_record_header(last_record, &RecordHeader { next_record: new_record_address, ..header })?;
+        }
+
+        // The new record is already reachable through the previous one, so only the first record of the chain
+        // has to be written right away; the new `last_record` is written with the next sync or system info change.
+        if self.system_info.first_record == BlockAddress::invalid() {
+            self.system_info.first_record = new_record_address;
+            self.system_info.last_record = new_record_address;
+            if let Err(error) = self.write_system_info() {
+                self.system_info.first_record = BlockAddress::invalid();
+                self.system_info.last_record = BlockAddress::invalid();
+                return Err(error);
+            }
+        }
+        else {
+            self.system_info.last_record = new_record_address;
+            self.system_info_dirty = true;
+        }
+
+        self.quotas.charge(key_bytes, block_bytes(record_size));
+        Ok(())
+    }
+
+    /// Writes an exact copy of the database to a new file at `path`, which must not exist yet.
+    /// Pages and the system info are written through on every operation, so the file already holds
+    /// the current contents and is copied as is; the copy shares no state with this database.
+    pub fn clone_to(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        self.page_manager.check_poisoned()?;
+        if self.system_info_dirty {
+            self.write_system_info()?;
+        }
+
+        let mut target = OpenOptions::new().create_new(true).write(true).open(path)?;
+        let mut throttle = self.maintenance_throttle();
+        let mut source = self.file.borrow_mut();
+        source.seek(SeekFrom::Start(0))?;
+        let mut buffer = vec![0; CLONE_CHUNK_SIZE];
+        loop {
+            let read = source.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+
+            target.write_all(&buffer[..read])?;
+            throttle.consume(read as u64);
+        }
+
+        target.sync_all()
+    }
+
+    /// Changes `Options::maintenance_bytes_per_second` for the following maintenance operations.
+    pub fn set_maintenance_bytes_per_second(&mut self, bytes_per_second: Option<u64>) {
+        self.maintenance_bytes_per_second = bytes_per_second;
+    }
+
+    fn maintenance_throttle(&self) -> Throttle {
+        Throttle::new(self.clock.clone(), self.maintenance_bytes_per_second)
+    }
+
+    /// Whether the file was closed cleanly before this open, so the crash recovery and `Options::verify_after_crash`
+    /// were skipped. New files count as clean; files written by older versions of the crate don't.
+    pub fn opened_after_clean_shutdown(&self) -> bool {
+        self.opened_after_clean_shutdown
+    }
+
+    /// Makes all writes so far durable, regardless of `sync_interval`.
+    /// With `SyncMode::None` this still syncs file contents.
+    pub fn sync(&mut self) -> Result<()> {
+        // A sync after a failed one may succeed without the lost writes ever reaching the file.
+        self.page_manager.check_poisoned()?;
+        if self.system_info_dirty {
+            self.write_system_info()?;
+        }
+
+        let _span = self.tracer.span(Phase::Sync);
+        let result = match self.sync_mode {
+            SyncMode::None | SyncMode::Data => self.file.borrow().sync_data(),
+            SyncMode::All => self.file.borrow().sync_all(),
+        };
+        // The OS may have dropped the writes that failed to sync, so what the file holds is unknown.
+        if let Err(error) = result {
+            self.page_manager.poison(format!("Sync failed: {}", error));
+            return Err(error);
+        }
+
+        self.last_sync_millis = self.clock.now_millis();
+        self.has_unsynced_writes = false;
+        Ok(())
+    }
+
+    fn sync_if_due(&mut self) -> Result<()> {
+        self.has_unsynced_writes = true;
+        if self.sync_mode == SyncMode::None {
+            return Ok(());
+        }
+
+        match self.sync_interval {
+            Some(interval) if self.clock.now_millis() < self.last_sync_millis + interval.as_millis() as u64 => Ok(()),
+            _ => self.sync(),
+        }
+    }
+
+    pub fn get(&mut self, key: &str) -> Result<Option<Vec<u8>>> {
+        if let Some((header, address)) = self.find(key.as_bytes())? {
+            let _span = self.tracer.span(Phase::Serialization);
+            let mut result = self.take_buffer(header.data_size as usize);
+            self.value_reader(&header, address)?.read_exact(&mut result)?;
+            Ok(Some(result))
+        }
+        else {
+            Ok(None)
+        }
+    }
+
+    /// `get` that fails with `ErrorKind::TimedOut` if the clock passes `deadline_millis`, in milliseconds since the
+    /// Unix epoch like `Clock::now_millis`, before the record is found. Overrides `Options::operation_timeout`.
+    pub fn get_with_deadline(&mut self, key: &str, deadline_millis: u64) -> Result<Option<Vec<u8>>> {
+        self.deadline_millis = Some(deadline_millis);
+        let found = self.find(key.as_bytes());
+        self.deadline_millis = None;
+        let Some((header, address)) = found? else {
+            return Ok(None);
+        };
+
+        let mut value = self.take_buffer(header.data_size as usize);
+        self.value_reader(&header, address)?.read_exact(&mut value)?;
+        Ok(Some(value))
+    }
+
+    /// Gives a value returned by `get`, `get_all` or an iterator back to `Options::buffer_pool` for reuse.
+    pub fn recycle(&self, buffer: Vec<u8>) {
+        self.give_back_buffer(buffer);
+    }
+
+    /// A buffer of `size` zeros from `Options::buffer_pool`, or a new one.
+    fn take_buffer(&self, size: usize) -> Vec<u8> {
+        let mut buffer = self.buffer_pool.as_ref().map_or_else(Vec::new, |pool| pool.take(size));
+        buffer.clear();
+        buffer.resize(size, 0);
+        buffer
+    }
+
+    fn give_back_buffer(&self, buffer: Vec<u8>) {
+        if let Some(pool) = &self.buffer_pool {
+            pool.give_back(buffer);
+        }
+    }
+
+    /// Returns all values of `key` in insertion order, see `Options::duplicate_keys`.
+    pub fn get_all(&mut self, key: &str) -> Result<Vec<Vec<u8>>> {
+        let mut values = Vec::new();
+        let mut start_address = self.system_info.first_record;
+        while let Some((header, address)) = self.find_from(start_address, key.as_bytes())? {
+            let mut value = self.take_buffer(header.data_size as usize);
+            self.value_reader(&header, address)?.read_exact(&mut value)?;
+            values.push(value);
+            start_address = header.next_record;
+        }
+
+        Ok(values)
+    }
+
+    /// Replaces the contents of `out` with the value of `key`, growing it as needed, so a reused vector
+    /// makes reads allocation-free. Returns `false` and leaves `out` empty if there is no such key.
+    pub fn get_into(&mut self, key: &str, out: &mut Vec<u8>) -> Result<bool> {
+        out.clear();
+        let Some((header, address)) = self.find(key.as_bytes())? else {
+            return Ok(false);
+        };
+
+        let _span = self.tracer.span(Phase::Serialization);
+        out.resize(header.data_size as usize, 0);
+        self.value_reader(&header, address)?.read_exact(out)?;
+        Ok(true)
+    }
+
+    /// Stores `data` under a key derived from its SHA-256 hash and returns the hash. Storing the same content
+    /// again finds the existing record and writes nothing.
+    pub fn put_blob(&mut self, data: &[u8]) -> Result<BlobHash> {
+        let hash = BlobHash::of(data);
+        self.set(&hash.key(), data)?;
+        Ok(hash)
+    }
+
+    /// Returns the content stored by `put_blob` under `hash`, checking that it still has that hash.
+    pub fn get_blob(&mut self, hash: &BlobHash) -> Result<Option<Vec<u8>>> {
+        let mut data = Vec::new();
+        if !self.get_into(&hash.key(), &mut data)? {
+            return Ok(None);
+        }
+
+        if BlobHash::of(&data) != *hash {
+            return Err(Error::new(ErrorKind::InvalidData, format!("Blob {} doesn't match its hash", hash)));
+        }
+
+        Ok(Some(data))
+    }
+
+    /// Returns the size and timestamps of the record with `key`, or `None` if there is no such key.
+    pub fn metadata(&mut self, key: &str) -> Result<Option<RecordMeta>> {
+        Ok(self.find(key.as_bytes())?.map(|(header, _)| RecordMeta::from(&header)))
+    }
+
+    /// Reads bytes of the value of `key` starting at `offset` into `buf`, without reading the rest of the value.
+    /// Returns the number of bytes read, which is less than `buf.len()` only at the end of the value,
+    /// or `None` if there is no such key.
+    pub fn read_at(&mut self, key: &str, offset: usize, buf: &mut [u8]) -> Result<Option<usize>> {
+        let Some((header, address)) = self.find(key.as_bytes())? else {
+            return Ok(None);
+        };
+
+        let data_size = header.data_size as usize;
+        if offset > data_size {
+            return Err(Error::new(ErrorKind::InvalidInput, "Read past the end of the value"));
+        }
+
+        let length = buf.len().min(data_size - offset);
+        let mut reader = self.value_reader(&header, address)?;
+        reader.skip(offset)?;
+        reader.read_exact(&mut buf[..length])?;
+        Ok(Some(length))
+    }
+
+    /// Overwrites `data.len()` bytes of the value of `key` starting at `offset`, in place.
+    /// The value's size can't change, so the range must lie within it. Returns `false` if there is no such key.
+    pub fn write_at(&mut self, key: &str, offset: usize, data: &[u8]) -> Result<bool> {
+        self.check_not_audit_key(key)?;
+        let Some((header, address)) = self.find(key.as_bytes())? else {
+            return Ok(false);
+        };
+
+        if offset.checked_add(data.len()).is_none_or(|end| end > header.data_size as usize) {
+            return Err(Error::new(ErrorKind::InvalidInput, "Write past the end of the value"));
+        }
+
+        if header.flags & RECORD_FLAG_SHARED_VALUE != 0 {
+            return Err(Error::new(ErrorKind::Unsupported, "Partial writes to deduplicated values aren't supported"));
+        }
+
+        {
+            let mut reader = self.value_reader(&header, address)?;
+            reader.skip(offset)?;
+            reader.overwrite(data)?;
+        }
+
+        let updated_millis = self.clock.now_millis();
+        self.write_record_header(address, &RecordHeader { updated_millis, ..header })?;
+        self.audit(AuditOp::WriteAt, key, None)?;
+        self.sync_if_due()?;
+        Ok(true)
+    }
+
+    /// Removes the record with `key`, or all of them with `Options::duplicate_keys`, and frees their blocks,
+    /// or moves them to the trash with `Options::trash`. Returns `false` if there is no such key.
+    pub fn delete(&mut self, key: &str) -> Result<bool> {
+        self.check_not_audit_key(key)?;
+        let trash_key = (self.trash && !key.starts_with(TRASH_KEY_PREFIX)).then(|| trash_key(key));
+        let mut deleted = false;
+        while let Some((header, address)) = self.find(key.as_bytes())? {
+            self.discard_record(&header, address, trash_key.as_deref(), !deleted)?;
+            deleted = true;
+            if !self.duplicate_keys {
+                break;
+            }
+        }
+
+        if deleted {
+            self.audit(AuditOp::Delete, key, None)?;
+            self.sync_if_due()?;
+        }
+
+        Ok(deleted)
+    }
+
+    /// Removes a record for `delete`, or moves it to `trash_key` when there is one. The trash only keeps the records
+    /// of the latest delete, so `first` empties it first.
+    fn discard_record(&mut self, header: &RecordHeader, address: BlockAddress, trash_key: Option<&str>, first: bool)
+        -> Result<()> {
+        match trash_key {
+            Some(trash_key) => {
+                if first {
+                    self.remove_all(trash_key)?;
+                }
+
+                let address = self.rewrite_key(header, address, trash_key)?;
+                let header = self.read_record_header(address)?;
+                let updated_millis = self.clock.now_millis();
+                self.write_record_header(address, &RecordHeader { updated_millis, ..header })?;
+            },
+            None => self.remove_record(header, address)?,
+        }
+
+        self.write_system_info()
+    }
+
+    /// Moves the records of `key` that `delete` put in the trash back, with `updated_millis` set to now.
+    /// Fails with `ErrorKind::AlreadyExists` if the key exists again and returns `false` if the trash doesn't
+    /// have it.
+    pub fn restore(&mut self, key: &str) -> Result<bool> {
+        self.check_not_audit_key(key)?;
+        let trash_key = trash_key(key);
+        if self.find(trash_key.as_bytes())?.is_none() {
+            return Ok(false);
+        }
+
+        if self.find(key.as_bytes())?.is_some() {
+            return Err(Error::new(ErrorKind::AlreadyExists, "A record with the key exists again"));
+        }
+
+        while let Some((header, address)) = self.find(trash_key.as_bytes())? {
+            let address = self.rewrite_key(&header, address, key)?;
+            let header = self.read_record_header(address)?;
+            let updated_millis = self.clock.now_millis();
+            self.write_record_header(address, &RecordHeader { updated_millis, ..header })?;
+            self.write_system_info()?;
+        }
+
+        self.audit(AuditOp::Rename, &trash_key, Some(key))?;
+        self.sync_if_due()?;
+        Ok(true)
+    }
+
+    /// Removes the records that have been in the trash for at least `older_than`; `Duration::ZERO` empties it.
+    /// Returns the number of removed records.
+    pub fn purge_trash(&mut self, older_than: Duration) -> Result<usize> {
+        let started_millis = self.clock.now_millis();
+        let deleted_before = started_millis.saturating_sub(older_than.as_millis() as u64);
+        let result = self.retain(|key, meta| !key.starts_with(TRASH_KEY_PREFIX) || meta.updated_millis > deleted_before);
+        self.log_maintenance(MaintenanceOp::PurgeTrash, started_millis, result, |&removed| (removed as u64, 0))
+    }
+
+    /// Reports what `purge_trash` would remove.
+    pub fn purge_trash_dry_run(&mut self, older_than: Duration) -> Result<DryRunReport> {
+        let deleted_before = self.clock.now_millis().saturating_sub(older_than.as_millis() as u64);
+        self.retain_dry_run(|key, meta| !key.starts_with(TRASH_KEY_PREFIX) || meta.updated_millis > deleted_before)
+    }
+
+    /// Removes the record with `key` and the value `data`, e.g. one of the values of a key with
+    /// `Options::duplicate_keys`. Returns `false` if there is no such record.
+    pub fn delete_value(&mut self, key: &str, data: &[u8]) -> Result<bool> {
+        self.check_not_audit_key(key)?;
+        let Some((header, address)) = self.find_value(key.as_bytes(), data)? else {
+            return Ok(false);
+        };
+
+        self.remove_record(&header, address)?;
+        self.write_system_info()?;
+        self.audit(AuditOp::Delete, key, None)?;
+        self.sync_if_due()?;
+        Ok(true)
+    }
+
+    /// Removes all records whose keys start with `prefix` in a single pass over the chain.
+    /// Returns the number of removed records.
+    pub fn delete_prefix(&mut self, prefix: &str) -> Result<usize> {
+        let prefix = prefix.as_bytes();
+        // The audit log needs the whole keys of removed records; otherwise reading their prefixes is enough.
+        let audited = self.audit.is_some();
+        let mut removed_keys = Vec::new();
+        let mut removed = 0;
+        let mut address = self.system_info.first_record;
+        while address != BlockAddress::invalid() {
+            let mut reader = PageReader::new(&mut self.page_manager, address)?;
+            let header = read_linked_header(&mut reader, self.auth_key.as_deref())?;
+            let read_size = if audited { header.key_size as usize } else { prefix.len() };
+            let matches = header.key_size as usize >= prefix.len() && {
+                if self.key_buffer.len() < read_size {
+                    self.key_buffer.resize(read_size, 0);
+                }
+
+                let key = &mut self.key_buffer[..read_size];
+                reader.read_exact(key)?;
+                key.starts_with(prefix) && !(audited && key.starts_with(AUDIT_KEY_PREFIX.as_bytes()))
+            };
+            drop(reader);
+
+            if matches {
+                if audited {
+                    removed_keys.push(String::from_utf8_lossy(&self.key_buffer[..read_size]).into_owned());
+                }
+
+                self.remove_record(&header, address)?;
+                removed += 1;
+            }
+
+            address = header.next_record;
+        }
+
+        if removed > 0 {
+            self.write_system_info()?;
+            for key in &removed_keys {
+                self.audit(AuditOp::Delete, key, None)?;
+            }
+
+            self.sync_if_due()?;
+        }
+
+        Ok(removed)
+    }
+
+    /// Removes every record for which `keep` returns `false`, in a single pass over the chain,
+    /// writing the system info once at the end. Returns the number of removed records.
+    pub fn retain(&mut self, keep: impl FnMut(&str, &RecordMeta) -> bool) -> Result<usize> {
+        Ok(self.retain_records(keep, true)?.records as usize)
+    }
+
+    /// Reports what `retain` would remove.
+    pub fn retain_dry_run(&mut self, keep: impl FnMut(&str, &RecordMeta) -> bool) -> Result<DryRunReport> {
+        self.retain_records(keep, false)
+    }
+
+    fn retain_records(&mut self, mut keep: impl FnMut(&str, &RecordMeta) -> bool, apply: bool)
+        -> Result<DryRunReport> {
+        let audited = self.audit.is_some();
+        let mut removed_keys = Vec::new();
+        let mut removed = DryRunReport::default();
+        let mut address = self.system_info.first_record;
+        while address != BlockAddress::invalid() {
+            let mut reader = PageReader::new(&mut self.page_manager, address)?;
+            let header = read_linked_header(&mut reader, self.auth_key.as_deref())?;
+            let key_size = header.key_size as usize;
+            if self.key_buffer.len() < key_size {
+                self.key_buffer.resize(key_size, 0);
+            }
+
+            reader.read_exact(&mut self.key_buffer[..key_size])?;
+            drop(reader);
+
+            let key = std::str::from_utf8(&self.key_buffer[..key_size]).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+            if audited && key.starts_with(AUDIT_KEY_PREFIX) {
+                address = header.next_record;
+                continue;
+            }
+
+            if !keep(key, &RecordMeta::from(&header)) {
+                removed.records += 1;
+                removed.bytes += block_bytes(header.record_size());
+                if apply {
+                    if audited {
+                        removed_keys.push(key.to_string());
+                    }
+
+                    self.remove_record(&header, address)?;
+                }
+            }
+
+            address = header.next_record;
+        }
+
+        if apply && removed.records > 0 {
+            self.write_system_info()?;
+            for key in &removed_keys {
+                self.audit(AuditOp::Delete, key, None)?;
+            }
+
+            self.sync_if_due()?;
+        }
+
+        Ok(removed)
+    }
+
+    /// Removes all records. Unlike deleting them one by one, this drops the pages wholesale and shrinks the file.
+    pub fn clear(&mut self) -> Result<()> {
+        if self.audit.is_some() {
+            return Err(Error::new(ErrorKind::PermissionDenied, "Clearing would remove the audit log"));
+        }
+
+        self.page_manager.clear()?;
+        if let Some(shared_values) = &mut self.shared_values {
+            *shared_values = SharedValues::default();
+        }
+
+        self.quotas.reset_usage();
+        self.system_info.first_record = BlockAddress::invalid();
+        self.system_info.last_record = BlockAddress::invalid();
+        self.write_system_info()?;
+        self.sync_if_due()
+    }
+
+    /// Removes every record with `key`, leaving `system_info` to be written by the caller.
+    fn remove_all(&mut self, key: &str) -> Result<()> {
+        while let Some((header, address)) = self.find(key.as_bytes())? {
+            self.remove_record(&header, address)?;
+        }
+
+        Ok(())
+    }
+
+    /// Unlinks a record from the chain and frees its blocks, leaving `system_info` to be written by the caller.
+    fn remove_record(&mut self, header: &RecordHeader, address: BlockAddress) -> Result<()> {
+        if !self.quotas.is_empty() {
+            let key = self.read_key(header, address)?;
+            self.quotas.release(&key, block_bytes(header.record_size()));
+            self.give_back_buffer(key);
+        }
+
+        self.link_neighbours(header, header.next_record, header.prev_record)?;
+        if header.flags & RECORD_FLAG_SHARED_VALUE != 0 {
+            let value_address = self.shared_value_address(header, address)?;
+            dedup::release(&mut self.page_manager, self.shared_values.as_mut(), value_address)?;
+        }
+
+        free_chain(&mut self.page_manager, address)?;
+        Ok(())
+    }
+
+    /// Points the records around the one described by `header` (or the ends of the chain) at new addresses:
+    /// the previous record's `next_record` at `after_prev` and the next record's `prev_record` at `before_next`.
+    fn link_neighbours(&mut self, header: &RecordHeader, after_prev: BlockAddress, before_next: BlockAddress)
+        -> Result<()> {
+        if header.prev_record == BlockAddress::invalid() {
+            self.system_info.first_record = after_prev;
+        }
+        else {
+            let prev = self.read_record_header(header.prev_record)?;
+            self.write_record_header(header.prev_record, &RecordHeader { next_record: after_prev, ..prev })?;
+        }
+
+        if header.next_record == BlockAddress::invalid() {
+            self.system_info.last_record = before_next;
+        }
+        else {
+            let next = self.read_record_header(header.next_record)?;
+            self.write_record_header(header.next_record, &RecordHeader { prev_record: before_next, ..next })?;
+        }
+
+        Ok(())
+    }
+
+    /// Gives the record with `old_key` the key `new_key`, keeping its value, timestamps and place in insertion order.
+    /// A key of the same length is overwritten in place; otherwise the record is rewritten, which copies the stored
+    /// value unless it's shared. Returns `false` if there is no such key.
+    pub fn rename(&mut self, old_key: &str, new_key: &str) -> Result<bool> {
+        self.check_not_audit_key(old_key)?;
+        self.check_not_audit_key(new_key)?;
+        let Some((header, address)) = self.find(old_key.as_bytes())? else {
+            return Ok(false);
+        };
+
+        if old_key == new_key {
+            return Ok(true);
+        }
+
+        if self.find(new_key.as_bytes())?.is_some() {
+            return Err(Error::new(ErrorKind::AlreadyExists, "A record with the new key already exists"));
+        }
+
+        if self.rewrite_key(&header, address, new_key)? != address {
+            self.write_system_info()?;
+        }
+
+        self.audit(AuditOp::Rename, old_key, Some(new_key))?;
+        self.sync_if_due()?;
+        Ok(true)
+    }
+
+    /// Gives the record at `address` the key `new_key` like `rename` and returns its address afterwards,
+    /// leaving `system_info` to be written by the caller.
+    /// Quotas count the record under its new key, but don't stop it from moving there.
+    fn rewrite_key(&mut self, header: &RecordHeader, address: BlockAddress, new_key: &str) -> Result<BlockAddress> {
+        if !self.quotas.is_empty() {
+            let old_key = self.read_key(header, address)?;
+            self.quotas.release(&old_key, block_bytes(header.record_size()));
+            self.give_back_buffer(old_key);
+            let new_header = RecordHeader { key_size: new_key.len() as i32, ..header.clone() };
+            self.quotas.charge(new_key.as_bytes(), block_bytes(new_header.record_size()));
+        }
+
+        if new_key.len() == header.key_size as usize {
+            let mut reader = PageReader::new(&mut self.page_manager, address)?;
+            reader.skip(RecordHeader::size_in_buffer())?;
+            reader.overwrite(new_key.as_bytes())?;
+            return Ok(address);
+        }
+
+        let mut stored_value = self.take_buffer(header.stored_value_size());
+        {
+            let mut reader = PageReader::new(&mut self.page_manager, address)?;
+            reader.skip(RecordHeader::size_in_buffer() + header.key_size as usize)?;
+            reader.read_exact(&mut stored_value)?;
+        }
+
+        let new_address = {
+            let mut writer = PageWriter::with_capacity(&mut self.page_manager,
+                RecordHeader::size_in_buffer() + new_key.len() + stored_value.len())?;
+            writer.write_structure(&RecordHeader { key_size: new_key.len() as i32, ..header.clone() }
+                .sealed(self.auth_key.as_deref()))?;
+            writer.write_all(new_key.as_bytes())?;
+            writer.write_all(&stored_value)?;
+            writer.finish()?
+        };
+        self.give_back_buffer(stored_value);
+
+        self.link_neighbours(header, new_address, new_address)?;
+        free_chain(&mut self.page_manager, address)?;
+        Ok(new_address)
+    }
+
+    /// Rewrites the record with `key` into blocks on as few pages as possible, if its blocks are spread over more
+    /// pages than needed, so reading it loads fewer pages. A shared value stays where it is.
+    /// Returns `false` if the record was already compact or there is no such key.
+    pub fn defragment(&mut self, key: &str) -> Result<bool> {
+        let Some((header, address)) = self.find(key.as_bytes())? else {
+            return Ok(false);
+        };
+
+        let moved = self.defragment_record(&header, address)?;
+        if moved {
+            self.write_system_info()?;
+            self.sync_if_due()?;
+        }
+
+        Ok(moved)
+    }
+
+    /// Defragments every record, see `defragment`. Returns the number of rewritten records.
+    pub fn defragment_all(&mut self) -> Result<usize> {
+        self.defragment_all_with_progress(progress::ignore)
+    }
+
+    /// `defragment_all` that reports every checked record to `progress`. When `progress` breaks, the records
+    /// rewritten so far stay rewritten and the call fails with a `Cancelled` error.
+    pub fn defragment_all_with_progress(&mut self, progress: impl FnMut(Progress) -> ControlFlow<()>)
+        -> Result<usize> {
+        let started_millis = self.clock.now_millis();
+        let result = self.defragment_records(progress);
+        self.log_maintenance(MaintenanceOp::Defragment, started_millis, result, |&moved| (moved as u64, 0))
+    }
+
+    fn defragment_records(&mut self, mut progress: impl FnMut(Progress) -> ControlFlow<()>) -> Result<usize> {
+        let mut moved = 0;
+        let mut checked = 0;
+        let mut throttle = self.maintenance_throttle();
+        let mut address = self.system_info.first_record;
+        let result = loop {
+            if address == BlockAddress::invalid() {
+                break Ok(());
+            }
+
+            let header = self.read_record_header(address)?;
+            if self.defragment_record(&header, address)? {
+                moved += 1;
+                // Read once and written once.
+                throttle.consume(2 * header.record_size() as u64);
+            }
+            else {
+                throttle.consume(BLOCK_SIZE as u64);
+            }
+
+            checked += 1;
+            address = header.next_record;
+            if let Err(error) = progress::report(&mut progress, checked, None) {
+                break Err(error);
+            }
+        };
+
+        if moved > 0 {
+            self.write_system_info()?;
+            self.sync_if_due()?;
+        }
+
+        result.map(|_| moved)
+    }
+
+    /// Reports the records `defragment_all` would rewrite.
+    pub fn defragment_all_dry_run(&mut self) -> Result<DryRunReport> {
+        let mut report = DryRunReport::default();
+        let mut throttle = self.maintenance_throttle();
+        let mut address = self.system_info.first_record;
+        while address != BlockAddress::invalid() {
+            let header = self.read_record_header(address)?;
+            if self.is_fragmented(address)? {
+                report.records += 1;
+                report.bytes += block_bytes(header.record_size());
+            }
+
+            throttle.consume(BLOCK_SIZE as u64);
+            address = header.next_record;
+        }
+
+        Ok(report)
+    }
+
+    /// Whether the blocks of the record at `address` are spread over more pages than needed.
+    fn is_fragmented(&mut self, address: BlockAddress) -> Result<bool> {
+        let chain = block_chain(&mut self.page_manager, address)?;
+        let mut pages: Vec<i32> = chain.iter().map(|a| a.page_index).collect();
+        pages.sort_unstable();
+        pages.dedup();
+        Ok(pages.len() > chain.len().div_ceil(PAGE_BLOCK_COUNT))
+    }
+
+    fn defragment_record(&mut self, header: &RecordHeader, address: BlockAddress) -> Result<bool> {
+        if !self.is_fragmented(address)? {
+            return Ok(false);
+        }
+
+        let mut record = self.take_buffer(header.record_size());
+        PageReader::new(&mut self.page_manager, address)?.read_exact(&mut record)?;
+
+        let new_address = {
+            let mut writer = PageWriter::with_capacity(&mut self.page_manager, record.len())?;
+            writer.write_all(&record)?;
+            writer.finish()?
+        };
+        self.give_back_buffer(record);
+
+        self.link_neighbours(header, new_address, new_address)?;
+        free_chain(&mut self.page_manager, address)?;
+        Ok(true)
+    }
+
+    /// Reads the value of `key` into the start of `buffer`. Fails with `ErrorKind::InvalidInput` if the value doesn't
+    /// fit. Returns `false` if there is no such key.
+    pub fn get_to_buffer(&mut self, key: &str, buffer: &mut [u8]) -> Result<bool> {
+        if let Some((header, address)) = self.find(key.as_bytes())? {
+            if buffer.len() < header.data_size as usize {
+                return Err(Error::new(ErrorKind::InvalidInput,
+                    format!("The value takes {:?} bytes, the buffer holds {:?}", header.data_size, buffer.len())));
+            }
+
+            let _span = self.tracer.span(Phase::Serialization);
+            let mut reader = self.value_reader(&header, address)?;
+            reader.read_exact(&mut buffer[..header.data_size as usize])?;
+            Ok(true)
+        }
+        else {
+            Ok(false)
+        }
+    }
+
+    /// Iterates over all records as `(key, value)` pairs, in insertion order.
+    pub fn iter(&mut self) -> Iter<'_> {
+        self.iter_insertion_order()
+    }
+
+    /// Iterates over all records as `(key, value)` pairs in the order their keys were first inserted.
+    /// Records form a chain in that order; every operation that moves or removes records keeps it,
+    /// so the database can serve as a durable log of keyed events. Use `rev()` to start from the newest record.
+    pub fn iter_insertion_order(&mut self) -> Iter<'_> {
+        let DbSystemInfo { first_record, last_record, .. } = self.system_info;
+        Iter { db: self, next_record: first_record, next_back_record: last_record }
+    }
+
+    /// Iterates in insertion order over the records created or changed at `since_millis` or later, e.g. to send
+    /// the changes since the last sync to another system. There is no time index, so every record header is read,
+    /// but keys and values only of the matching records. Deleted records aren't reported.
+    pub fn iter_modified_since(&mut self, since_millis: u64) -> ModifiedSince<'_> {
+        let next_record = self.system_info.first_record;
+        ModifiedSince { db: self, next_record, since_millis }
+    }
+
+    /// Iterates in insertion order over the records after the one `cursor` is at, see `RecordCursor`. A record
+    /// moved by `defragment` keeps its place in the chain and is found again by its key. If the record was
+    /// removed or renamed, fails with `ErrorKind::NotFound`, as the remaining records can't be told apart from
+    /// those returned already.
+    pub fn iter_from(&mut self, cursor: &RecordCursor) -> Result<CursorIter<'_>> {
+        let next_record = match &cursor.last {
+            None => self.system_info.first_record,
+            Some(record) => self.locate_cursor_record(record)?.next_record,
+        };
+
+        Ok(CursorIter { db: self, next_record, cursor: cursor.clone() })
+    }
+
+    fn locate_cursor_record(&mut self, record: &CursorRecord) -> Result<RecordHeader> {
+        let key = record.key.as_bytes();
+        // The address may not hold a record anymore, and a removed record's blocks are only marked free, so its
+        // header may still be there.
+        let header = match self.page_manager.get_page(record.address.page_index)
+            .map(|page| page.is_block_busy(record.address.block_index))
+            .and_then(|busy| if busy { self.probe_record_header(record.address).map(Some) } else { Ok(None) }) {
+            Ok(header) => header,
+            Err(e) if e.kind() == ErrorKind::InvalidData => None,
+            Err(e) => return Err(e),
+        };
+        if let Some(header) = header {
+            if header.created_millis == record.created_millis && header.key_size as usize == key.len()
+                && self.key_starts_with(&header, record.address, key)? {
+                return Ok(header);
+            }
+        }
+
+        let mut start_address = self.system_info.first_record;
+        while let Some((header, _)) = self.find_from(start_address, key)? {
+            if header.created_millis == record.created_millis {
+                return Ok(header);
+            }
+
+            start_address = header.next_record;
+        }
+
+        Err(Error::new(ErrorKind::NotFound, format!("The record {:?} of the cursor was removed", record.key)))
+    }
+
+    /// Iterates over all records in the order they are stored in the file rather than in insertion order, so a full
+    /// export reads the file mostly sequentially instead of jumping along the chain. Finding the records reads every
+    /// page once up front.
+    pub fn iter_physical(&mut self) -> Result<PhysicalIter<'_>> {
+        // A busy block holds the start of a record if it holds a header with a valid checksum that is linked from
+        // the previous record, which tells record starts apart from values that contain copies of headers.
+        let mut headers = Vec::new();
+        for page_index in 0..self.page_manager.page_count()? {
+            let busy_blocks = {
+                let page = self.page_manager.get_page(page_index)?;
+                (0..PAGE_BLOCK_COUNT as u8).filter(|&b| page.is_block_busy(b)).collect::<Vec<_>>()
+            };
+
+            for block_index in busy_blocks {
+                let address = BlockAddress::new(page_index, block_index);
+                match self.probe_record_header(address) {
+                    Ok(header) => headers.push((address, header.prev_record, header.next_record)),
+                    Err(e) if e.kind() == ErrorKind::InvalidData => {},
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        let next_records: HashMap<BlockAddress, BlockAddress> =
+            headers.iter().map(|&(address, _, next_record)| (address, next_record)).collect();
+        let first_record = self.system_info.first_record;
+        let records: VecDeque<BlockAddress> = headers.into_iter()
+            .filter(|&(address, prev_record, _)| if prev_record == BlockAddress::invalid() {
+                address == first_record
+            }
+            else {
+                next_records.get(&prev_record) == Some(&address)
+            })
+            .map(|(address, _, _)| address)
+            .collect();
+        Ok(PhysicalIter { db: self, records })
+    }
+
+    /// Hash of all records that is equal for two databases exactly when they hold the same keys and values,
+    /// regardless of insertion order. Same as the only hash of `range_hashes(&[])`.
+    pub fn merkle_root(&mut self) -> Result<[u8; 32]> {
+        Ok(self.range_hashes(&[])?[0])
+    }
+
+    /// Hashes of the key ranges split at `boundaries`, which must be ascending: the first range holds the keys
+    /// before `boundaries[0]`, range `i` the keys from `boundaries[i - 1]` up to `boundaries[i]` and the last range
+    /// the keys from the last boundary on. Two replicas that compare the hashes of the same boundaries find out
+    /// which ranges differ, then split only those further or exchange their records. Reads all records once.
+    pub fn range_hashes(&mut self, boundaries: &[&str]) -> Result<Vec<[u8; 32]>> {
+        if boundaries.windows(2).any(|pair| pair[0] > pair[1]) {
+            return Err(Error::new(ErrorKind::InvalidInput, "Range boundaries must be ascending"));
+        }
+
+        let mut leaves = self.iter()
+            .map(|record| record.map(|(key, value)| {
+                let leaf = merkle::leaf_hash(&key, &value);
+                (key, leaf)
+            }))
+            .collect::<Result<Vec<_>>>()?;
+        // Values of the same key, see `Options::duplicate_keys`, are ordered by their hash.
+        leaves.sort_unstable();
+        Ok(merkle::range_hashes(&leaves, boundaries))
+    }
+
+    /// The earliest inserted record.
+    pub fn first(&mut self) -> Result<Option<(String, Vec<u8>)>> {
+        self.record_at(self.system_info.first_record)
+    }
+
+    /// The most recently inserted record.
+    pub fn last(&mut self) -> Result<Option<(String, Vec<u8>)>> {
+        self.record_at(self.system_info.last_record)
+    }
+
+    fn record_at(&mut self, address: BlockAddress) -> Result<Option<(String, Vec<u8>)>> {
+        if address == BlockAddress::invalid() {
+            return Ok(None);
+        }
+
+        let (_, key, data) = self.read_record(address)?;
+        Ok(Some((key, data)))
+    }
+
+    /// Checks every record: header checksums, links in both directions, that the blocks hold the whole key and value
+    /// and that keys are UTF-8. Stops at the first damaged record, whose `next_record` can't be trusted,
+    /// with an `InvalidData` error naming its address. Returns the number of records.
+    pub fn verify(&mut self) -> Result<usize> {
+        self.verify_with_progress(progress::ignore)
+    }
+
+    /// `verify` that reports every checked record to `progress`, which can cancel the check.
+    pub fn verify_with_progress(&mut self, progress: impl FnMut(Progress) -> ControlFlow<()>) -> Result<usize> {
+        let started_millis = self.clock.now_millis();
+        let result = self.verify_records(progress);
+        self.log_maintenance(MaintenanceOp::Verify, started_millis, result, |&records| (records as u64, 0))
+    }
+
+    fn verify_records(&mut self, mut progress: impl FnMut(Progress) -> ControlFlow<()>) -> Result<usize> {
+        let mut count = 0;
+        let mut throttle = self.maintenance_throttle();
+        let cached_before = self.cached_page_set();
+        let mut prev_record = BlockAddress::invalid();
+        let mut address = self.system_info.first_record;
+        while address != BlockAddress::invalid() {
+            let (header, _) = self.verify_record(address, prev_record)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, format!("Record at {}: {}", address, e)))?;
+            throttle.consume(header.record_size() as u64);
+            self.trim_scan_cache(&cached_before);
+            count += 1;
+            prev_record = address;
+            address = header.next_record;
+            progress::report(&mut progress, count as u64, None)?;
+        }
+
+        if prev_record != self.system_info.last_record {
+            return Err(Error::new(ErrorKind::InvalidData,
+                format!("Chain ends at {} instead of the last record {}", prev_record, self.system_info.last_record)));
+        }
+
+        Ok(count)
+    }
+
+    /// Checks records one by one like `verify`, but reports damage per record and keeps going: once the chain
+    /// can't be followed forward, the records after the damage are recovered by following `prev_record` back from
+    /// the last record. Only records between two damaged ones are lost, and they are reported.
+    pub fn verify_chain(&mut self) -> VerifyChain<'_> {
+        let next_record = self.system_info.first_record;
+        VerifyChain { db: self, next_record, prev_record: BlockAddress::invalid(), salvaged: None }
+    }
+
+    fn verify_record(&mut self, address: BlockAddress, expected_prev_record: BlockAddress)
+        -> Result<(RecordHeader, String)> {
+        // Damage is what's being looked for, so it's reported without poisoning the database.
+        self.probe_record_header(address)?;
+        let (header, key, value) = self.read_record(address)?;
+        self.give_back_buffer(value);
+        if header.prev_record != expected_prev_record {
+            return Err(Error::new(ErrorKind::InvalidData,
+                format!("prev_record is {} instead of {}", header.prev_record, expected_prev_record)));
+        }
+
+        Ok((header, key))
+    }
+
+    /// Frees busy blocks that no record or shared value uses, e.g. left behind by a crash in the middle of a write.
+    /// Returns the number of reclaimed bytes.
+    pub fn gc(&mut self) -> Result<usize> {
+        let started_millis = self.clock.now_millis();
+        let result = self.collect_garbage(true).map(|blocks| blocks * BLOCK_SIZE);
+        self.log_maintenance(MaintenanceOp::Gc, started_millis, result, |&bytes| (0, bytes as u64))
+    }
+
+    /// Reports the bytes `gc` would reclaim. `records` is always 0, as the blocks `gc` frees belong to no record.
+    pub fn gc_dry_run(&mut self) -> Result<DryRunReport> {
+        Ok(DryRunReport { records: 0, bytes: (self.collect_garbage(false)? * BLOCK_SIZE) as u64 })
+    }
+
+    /// Returns the number of unreachable blocks, freeing them if `apply` is set.
+    fn collect_garbage(&mut self, apply: bool) -> Result<usize> {
+        let mut throttle = self.maintenance_throttle();
+        let cached_before = self.cached_page_set();
+        let page_count = self.page_manager.page_count()?;
+        let mut freed_blocks = 0;
+        for partition_start in (0..page_count).step_by(GC_PARTITION_PAGES as usize) {
+            let partition = partition_start..page_count.min(partition_start.saturating_add(GC_PARTITION_PAGES));
+            // Bit `b` of `reachable[p]` is set if block `b` of page `partition.start + p` is used.
+            let mut reachable = vec![0_u64; partition.len()];
+            let mut address = self.system_info.first_record;
+            while address != BlockAddress::invalid() {
+                let header = self.read_record_header(address)?;
+                let mut chain = block_chain(&mut self.page_manager, address)?;
+                if header.flags & RECORD_FLAG_SHARED_VALUE != 0 {
+                    let value_address = self.shared_value_address(&header, address)?;
+                    chain.extend(block_chain(&mut self.page_manager, value_address)?);
+                }
+
+                throttle.consume((chain.len() * BLOCK_SIZE) as u64);
+                for block in chain.iter().filter(|block| partition.contains(&block.page_index)) {
+                    reachable[(block.page_index - partition.start) as usize] |= 1 << block.block_index;
+                }
+
+                self.trim_scan_cache(&cached_before);
+                address = header.next_record;
+            }
+
+            for page_index in partition.clone() {
+                throttle.consume(PAGE_SIZE as u64);
+                let mut page = self.page_manager.get_page(page_index)?;
+                let used_blocks = reachable[(page_index - partition.start) as usize];
+                for block_index in 0..PAGE_BLOCK_COUNT as u8 {
+                    if page.is_block_busy(block_index) && used_blocks & (1 << block_index) == 0 {
+                        if apply {
+                            page.free_block(block_index)?;
+                        }
+
+                        freed_blocks += 1;
+                    }
+                }
+
+                page.commit()?;
+                drop(page);
+                self.trim_scan_cache(&cached_before);
+            }
+        }
+
+        if apply && freed_blocks > 0 && self.shared_values.is_some() {
+            self.load_shared_values()?;
+        }
+
+        Ok(freed_blocks)
+    }
+
+    fn cached_page_set(&self) -> HashSet<i32> {
+        self.page_manager.cached_page_indexes().into_iter().collect()
+    }
+
+    /// Evicts the pages a scan loaded once there are more than `SCAN_CACHE_PAGES` of them, keeping the pages
+    /// that were cached before the scan.
+    fn trim_scan_cache(&mut self, cached_before: &HashSet<i32>) {
+        if self.page_manager.cached_page_count() > cached_before.len() + SCAN_CACHE_PAGES {
+            self.page_manager.evict_pages_where(|index| !cached_before.contains(&index));
+        }
+    }
+
+    /// Returns `true` after a page write failed where the error couldn't be returned, a sync failed or a record
+    /// header reached through the chain failed its checksum. Every later operation that touches the file fails then
+    /// with a `Poisoned` error, until `reopen`.
+    pub fn is_poisoned(&self) -> bool {
+        self.page_manager.is_poisoned()
+    }
+
+    /// Continues with what the file holds, e.g. after the database was poisoned: drops the cached pages, runs the
+    /// recovery of an open after a crash and recounts what is derived from the records, i.e. shared values, the
+    /// audit sequence and quota usage. Changes that didn't reach the file are lost, like in a crash.
+    pub fn reopen(&mut self) -> Result<()> {
+        let started_millis = self.clock.now_millis();
+        let result = self.reopen_file();
+        self.log_maintenance(MaintenanceOp::Reopen, started_millis, result, |&records| (records, 0)).map(|_| ())
+    }
+
+    fn reopen_file(&mut self) -> Result<u64> {
+        self.page_manager.reload()?;
+        self.read_system_info()?;
+        self.authenticate_system_info()?;
+        self.system_info_dirty = false;
+        let recovered = self.recover_last_record()?;
+        self.authenticate_records()?;
+        if self.shared_values.is_some() {
+            self.load_shared_values()?;
+        }
+
+        if let Some(AuditLog { actor, .. }) = self.audit.take() {
+            self.open_audit_log()?;
+            self.set_actor(actor);
+        }
+
+        for prefix in self.quotas.prefixes() {
+            let max_bytes = self.quotas.usage(&prefix).map(|usage| usage.max_bytes);
+            self.set_quota(&prefix, max_bytes)?;
+        }
+
+        if let Some(log) = self.maintenance.take() {
+            let next_sequence = self.next_sequence(maintenance::parse_sequence)?;
+            self.maintenance = Some(MaintenanceLog { next_sequence: next_sequence.max(log.next_sequence) });
+        }
+
+        Ok(recovered)
+    }
+
+    /// Collects record counts and the distributions of key sizes, value sizes and blocks per record,
+    /// e.g. to see whether values are large enough to benefit from `Options::dedup_values`.
+    pub fn stats(&mut self) -> Result<Stats> {
+        let mut stats = Stats::default();
+        let mut address = self.system_info.first_record;
+        while address != BlockAddress::invalid() {
+            let header = self.read_record_header(address)?;
+            stats.record_count += 1;
+            if header.flags & RECORD_FLAG_SHARED_VALUE != 0 {
+                stats.shared_value_records += 1;
+            }
+
+            stats.key_sizes.record(header.key_size as u64);
+            stats.value_sizes.record(header.data_size as u64);
+            stats.blocks_per_record.record(block_chain(&mut self.page_manager, address)?.len() as u64);
+            address = header.next_record;
+        }
+
+        let page_count = self.page_manager.page_count()?;
+        stats.page_count = page_count as u64;
+        for page_index in 0..page_count {
+            stats.free_blocks += self.page_manager.get_page(page_index)?.free_block_count() as u64;
+        }
+
+        Ok(stats)
+    }
+
+    /// Counts the records and their bytes grouped by the first `depth` segments of their keys split at `delimiter`,
+    /// like `du -s` over a keyspace, e.g. to find what grew when a file grows unexpectedly. A group's key is the
+    /// common prefix including the last delimiter, so it can be passed to `estimate_size` or `delete_prefix`;
+    /// keys with fewer segments form a group of their own, and `depth` 0 puts all records into the group `""`.
+    /// Bytes are counted like in `SizeEstimate::bytes`.
+    pub fn stats_by_prefix(&mut self, depth: usize, delimiter: char) -> Result<BTreeMap<String, SizeEstimate>> {
+        let mut groups: BTreeMap<String, SizeEstimate> = BTreeMap::new();
+        let mut address = self.system_info.first_record;
+        while address != BlockAddress::invalid() {
+            let header = self.read_record_header(address)?;
+            let key = self.read_key(&header, address)?;
+            let prefix = if depth == 0 {
+                String::new()
+            }
+            else {
+                let key = String::from_utf8_lossy(&key);
+                let end = key.match_indices(delimiter).nth(depth - 1)
+                    .map_or(key.len(), |(index, _)| index + delimiter.len_utf8());
+                key[..end].to_string()
+            };
+            self.give_back_buffer(key);
+
+            let group = groups.entry(prefix).or_insert(SizeEstimate { exact: true, ..Default::default() });
+            group.record_count += 1;
+            group.bytes += block_bytes(header.record_size());
+            address = header.next_record;
+        }
+
+        Ok(groups)
+    }
+
+    /// Estimates the storage taken by the records whose keys start with `prefix`, e.g. to meter tenants that have
+    /// their own prefixes. Small files are counted exactly; in bigger ones the records starting on a sample of pages
+    /// are counted and scaled up to the whole file, which reads a fixed number of pages however big the file is.
+    pub fn estimate_size(&mut self, prefix: &str) -> Result<SizeEstimate> {
+        let page_count = self.page_manager.page_count()?;
+        if page_count <= EXACT_SIZE_PAGE_LIMIT {
+            return self.exact_size(prefix);
+        }
+
+        // A record starts in a busy block whose first bytes are a header with a valid checksum. Other blocks
+        // practically never pass the checksum, unless values contain copies of record headers.
+        let mut estimate = SizeEstimate::default();
+        for sample in 0..SIZE_SAMPLE_PAGES {
+            let page_index = (sample as i64 * page_count as i64 / SIZE_SAMPLE_PAGES as i64) as i32;
+            let busy_blocks = {
+                let page = self.page_manager.get_page(page_index)?;
+                (0..PAGE_BLOCK_COUNT as u8).filter(|&b| page.is_block_busy(b)).collect::<Vec<_>>()
+            };
+
+            for block_index in busy_blocks {
+                let address = BlockAddress::new(page_index, block_index);
+                let header = match self.probe_record_header(address) {
+                    Ok(header) => header,
+                    Err(e) if e.kind() == ErrorKind::InvalidData => continue,
+                    Err(e) => return Err(e),
+                };
+
+                if self.key_starts_with(&header, address, prefix.as_bytes())? {
+                    estimate.record_count += 1;
+                    estimate.bytes += block_bytes(header.record_size());
+                }
+            }
+        }
+
+        let scale = page_count as f64 / SIZE_SAMPLE_PAGES as f64;
+        estimate.record_count = (estimate.record_count as f64 * scale).round() as u64;
+        estimate.bytes = (estimate.bytes as f64 * scale).round() as u64;
+        Ok(estimate)
+    }
+
+    fn exact_size(&mut self, prefix: &str) -> Result<SizeEstimate> {
+        let mut size = SizeEstimate { exact: true, ..Default::default() };
+        let mut address = self.system_info.first_record;
+        while address != BlockAddress::invalid() {
+            let header = self.read_record_header(address)?;
+            if self.key_starts_with(&header, address, prefix.as_bytes())? {
+                size.record_count += 1;
+                size.bytes += block_bytes(header.record_size());
+            }
+
+            address = header.next_record;
+        }
+
+        Ok(size)
+    }
+
+    /// Limits the bytes the records under `prefix` may take, counted like `SizeEstimate::bytes`, or lifts the limit
+    /// with `None`. Writes of new records that would go over it fail with `ErrorKind::QuotaExceeded`; renames and
+    /// moves to the trash are counted but not refused. Setting a quota walks the record chain to count the current
+    /// usage, which may already be over the limit. Quotas hold until the database is closed.
+    pub fn set_quota(&mut self, prefix: &str, max_bytes: Option<u64>) -> Result<()> {
+        match max_bytes {
+            Some(max_bytes) => {
+                let used_bytes = self.exact_size(prefix)?.bytes;
+                self.quotas.set(prefix, QuotaUsage { used_bytes, max_bytes });
+            },
+            None => self.quotas.remove(prefix),
+        }
+
+        Ok(())
+    }
+
+    /// Returns the quota set for `prefix` with its current usage, or `None` if there is none.
+    pub fn quota_usage(&self, prefix: &str) -> Option<QuotaUsage> {
+        self.quotas.usage(prefix)
+    }
+
+    /// Whether the key of the record at `address` starts with `prefix`, reading no more of the key than that.
+    fn key_starts_with(&mut self, header: &RecordHeader, address: BlockAddress, prefix: &[u8]) -> Result<bool> {
+        if (header.key_size as usize) < prefix.len() {
+            return Ok(false);
+        }
+
+        if self.key_buffer.len() < prefix.len() {
+            self.key_buffer.resize(prefix.len(), 0);
+        }
+
+        let mut reader = PageReader::new(&mut self.page_manager, address)?;
+        reader.skip(RecordHeader::size_in_buffer())?;
+        reader.read_exact(&mut self.key_buffer[..prefix.len()])?;
+        Ok(&self.key_buffer[..prefix.len()] == prefix)
+    }
+
+    /// Runs `op` on the database and reports where its time went: finding the record, loading and writing pages,
+    /// copying keys and values, and syncing. Tracing only costs while the op runs.
+    pub fn trace_next_op<T>(&mut self, op: impl FnOnce(&mut Database) -> T) -> (T, OpTrace) {
+        self.tracer.start();
+        let start = Instant::now();
+        let result = op(self);
+        let total = start.elapsed();
+        (result, OpTrace { total, ..self.tracer.finish() })
+    }
+
+    /// Reports the memory used by cached pages.
+    pub fn cache_stats(&self) -> CacheStats {
+        let cached_pages = self.page_manager.cached_page_count();
+        CacheStats { cached_pages, cached_bytes: cached_pages * PAGE_SIZE }
+    }
+
+    /// Evicts cached pages until they take at most `target_bytes`, e.g. when the process is under memory pressure,
+    /// in the order of `Options::cache_policy`. Evicted pages are read from the file again when needed. Returns the
+    /// number of freed bytes.
+    pub fn shrink_cache(&mut self, target_bytes: usize) -> usize {
+        self.page_manager.evict_pages(target_bytes / PAGE_SIZE) * PAGE_SIZE
+    }
+
+    /// Loads the pages holding the records of `keys`, shared values included, into the page cache, e.g. to warm it
+    /// when a service starts instead of paying for cold reads on the first requests. Returns the number of pages.
+    /// Records are linked, so finding them walks the chain; pages the walk loaded only for other records are
+    /// evicted again, so the cache ends up with what it held before plus the preloaded pages.
+    pub fn preload<K: AsRef<str>>(&mut self, keys: impl IntoIterator<Item = K>) -> Result<usize> {
+        let keys: HashSet<Vec<u8>> = keys.into_iter().map(|key| key.as_ref().as_bytes().to_vec()).collect();
+        self.preload_matching(|key| keys.contains(key))
+    }
+
+    /// `preload` for the records whose keys start with `prefix`.
+    pub fn preload_prefix(&mut self, prefix: &str) -> Result<usize> {
+        self.preload_matching(|key| key.starts_with(prefix.as_bytes()))
+    }
+
+    fn preload_matching(&mut self, mut matches: impl FnMut(&[u8]) -> bool) -> Result<usize> {
+        let mut pages: BTreeSet<i32> = self.page_manager.cached_page_indexes().into_iter().collect();
+        let mut preloaded = BTreeSet::new();
+        let mut address = self.system_info.first_record;
+        while address != BlockAddress::invalid() {
+            let header = self.read_record_header(address)?;
+            let key = self.read_key(&header, address)?;
+            if matches(&key) {
+                preloaded.extend(block_chain(&mut self.page_manager, address)?.iter().map(|a| a.page_index));
+                if header.flags & RECORD_FLAG_SHARED_VALUE != 0 {
+                    let value_address = self.shared_value_address(&header, address)?;
+                    preloaded.extend(block_chain(&mut self.page_manager, value_address)?.iter().map(|a| a.page_index));
+                }
+            }
+
+            self.give_back_buffer(key);
+            address = header.next_record;
+        }
+
+        pages.extend(&preloaded);
+        self.page_manager.evict_pages_where(|index| !pages.contains(&index));
+        Ok(preloaded.len())
+    }
+
+    /// Decodes page `index` for debugging: block states, next block pointers and raw bytes.
+    pub fn inspect_page(&mut self, index: i32) -> Result<PageInfo> {
+        inspect::inspect_page(&mut self.page_manager, index)
+    }
+
+    /// Lists the blocks occupied by the record with `key`, or `None` if there is no such record.
+    pub fn record_chain(&mut self, key: &str) -> Result<Option<Vec<BlockAddress>>> {
+        match self.find(key.as_bytes())? {
+            Some((_, address)) => Ok(Some(block_chain(&mut self.page_manager, address)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Describes the physical layout: free blocks of every page and the blocks of every record.
+    pub fn debug_layout(&mut self) -> Result<DebugLayout> {
+        let mut records = Vec::new();
+        let mut address = self.system_info.first_record;
+        while address != BlockAddress::invalid() {
+            let (header, key, value) = self.read_record(address)?;
+            self.give_back_buffer(value);
+            records.push(RecordLayout {
+                address,
+                key_preview: inspect::key_preview(&key),
+                blocks: block_chain(&mut self.page_manager, address)?,
+            });
+            address = header.next_record;
+        }
+
+        Ok(DebugLayout { pages: inspect::page_layouts(&mut self.page_manager)?, records })
+    }
+
+    fn read_record(&mut self, address: BlockAddress) -> Result<(RecordHeader, String, Vec<u8>)> {
+        let mut reader = PageReader::new(&mut self.page_manager, address)?;
+        let header = read_linked_header(&mut reader, self.auth_key.as_deref())?;
+
+        let mut key = vec![0; header.key_size as usize];
+        reader.read_exact(&mut key)?;
+        let key = String::from_utf8(key).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+        let mut data = self.take_buffer(header.data_size as usize);
+        self.value_reader(&header, address)?.read_exact(&mut data)?;
+
+        Ok((header, key, data))
+    }
+
+    /// Reads the header from the first block of a record, where it always fits entirely. The chain can't be trusted
+    /// past a header that fails its checksum, so that poisons the database.
+    fn read_record_header(&mut self, address: BlockAddress) -> Result<RecordHeader> {
+        self.probe_record_header(address).inspect_err(|error| contain_corruption(&mut self.page_manager, error))
+    }
+
+    /// `read_record_header` for blocks that may not start a record, or records checked for damage: a checksum
+    /// mismatch is only returned.
+    fn probe_record_header(&mut self, address: BlockAddress) -> Result<RecordHeader> {
+        let page = self.page_manager.get_page(address.page_index)?;
+        let data = page.get_block_data(address.block_index, 0, RecordHeader::size_in_buffer())?;
+        RecordHeader::read(&mut &data[..])?.checked(self.auth_key.as_deref())
+    }
+
+    fn read_key(&mut self, header: &RecordHeader, address: BlockAddress) -> Result<Vec<u8>> {
+        let mut key = self.take_buffer(header.key_size as usize);
+        let mut reader = PageReader::new(&mut self.page_manager, address)?;
+        reader.skip(RecordHeader::size_in_buffer())?;
+        reader.read_exact(&mut key)?;
+        Ok(key)
+    }
+
+    fn write_record_header(&mut self, address: BlockAddress, header: &RecordHeader) -> Result<()> {
+        let mut buffer = [0_u8; RecordHeader::size_in_buffer()];
+        buffer.write_structure(&header.sealed(self.auth_key.as_deref()))?;
+        let mut page = self.page_manager.get_page(address.page_index)?;
+        page.set_block_data(address.block_index, &buffer, 0)?;
+        page.commit()
+    }
+
+    /// Opens a reader at the first byte of the record's value, following the reference of a shared value.
+    fn value_reader(&mut self, header: &RecordHeader, address: BlockAddress) -> Result<PageReader<'_>> {
+        let (value_address, skip) = if header.flags & RECORD_FLAG_SHARED_VALUE == 0 {
+            (address, RecordHeader::size_in_buffer() + header.key_size as usize)
+        }
+        else {
+            (self.shared_value_address(header, address)?, SharedValueHeader::size_in_buffer())
+        };
+
+        let mut reader = PageReader::new(&mut self.page_manager, value_address)?;
+        reader.skip(skip)?;
+        Ok(reader)
+    }
+
+    /// Reads the reference stored in place of the value of a record with `RECORD_FLAG_SHARED_VALUE`.
+    fn shared_value_address(&mut self, header: &RecordHeader, address: BlockAddress) -> Result<BlockAddress> {
+        let mut reader = PageReader::new(&mut self.page_manager, address)?;
+        reader.skip(RecordHeader::size_in_buffer() + header.key_size as usize)?;
+        reader.read_structure::<BlockAddress>()
+    }
+
+    /// Continues the audit log after its newest entry. Entries are only appended, so that's the first one found
+    /// walking the chain backwards.
+    fn open_audit_log(&mut self) -> Result<()> {
+        let next_sequence = self.next_sequence(audit::parse_sequence)?;
+        self.audit = Some(AuditLog { actor: String::new(), next_sequence });
+        Ok(())
+    }
+
+    /// The sequence number after that of the newest record whose key `parse_sequence` accepts, found by walking
+    /// the chain backwards.
+    fn next_sequence(&mut self, parse_sequence: fn(&str) -> Option<u64>) -> Result<u64> {
+        let mut address = self.system_info.last_record;
+        while address != BlockAddress::invalid() {
+            let mut reader = PageReader::new(&mut self.page_manager, address)?;
+            let header = read_linked_header(&mut reader, self.auth_key.as_deref())?;
+            let mut key = vec![0; header.key_size as usize];
+            reader.read_exact(&mut key)?;
+            if let Some(sequence) = std::str::from_utf8(&key).ok().and_then(parse_sequence) {
+                return Ok(sequence + 1);
+            }
+
+            address = header.prev_record;
+        }
+
+        Ok(0)
+    }
+
+    /// Returns the events of `Options::maintenance_log`, oldest first. Events are found in the file whether or not
+    /// it's opened with the option.
+    pub fn maintenance_log(&mut self) -> Result<Vec<MaintenanceEvent>> {
+        let mut events = Vec::new();
+        for record in self.iter() {
+            let (key, value) = record?;
+            if key.starts_with(MAINTENANCE_KEY_PREFIX) {
+                events.push(MaintenanceEvent::decode(&key, &value)?);
+            }
+        }
+
+        Ok(events)
+    }
+
+    /// Appends an event for a maintenance operation that started at `started_millis` and ended with `result` to
+    /// the maintenance log, if there is one, taking the counts of the event from a successful result. Returns
+    /// `result`, or the error of the append if only that failed.
+    fn log_maintenance<T>(&mut self, op: MaintenanceOp, started_millis: u64, result: Result<T>,
+        counts: impl FnOnce(&T) -> (u64, u64)) -> Result<T> {
+        let Some(log) = &mut self.maintenance else {
+            return result;
+        };
+
+        let (records, bytes) = result.as_ref().map_or((0, 0), counts);
+        let millis = self.clock.now_millis();
+        let event = MaintenanceEvent { sequence: log.next_sequence, millis,
+            duration_millis: millis.saturating_sub(started_millis), op, records, bytes,
+            error: result.as_ref().err().map(ToString::to_string) };
+        log.next_sequence += 1;
+        let logged = self.insert(event.record_key().as_bytes(), &event.encode(), millis);
+        let value = result?;
+        logged?;
+        self.sync_if_due()?;
+        Ok(value)
+    }
+
+    fn load_shared_values(&mut self) -> Result<()> {
+        let mut shared_values = SharedValues::default();
+        let mut address = self.system_info.first_record;
+        while address != BlockAddress::invalid() {
+            let mut reader = PageReader::new(&mut self.page_manager, address)?;
+            let header = read_linked_header(&mut reader, self.auth_key.as_deref())?;
+            if header.flags & RECORD_FLAG_SHARED_VALUE != 0 {
+                reader.skip(header.key_size as usize)?;
+                let value_address = reader.read_structure::<BlockAddress>()?;
+                drop(reader);
+                shared_values.register(&mut self.page_manager, value_address)?;
+            }
+
+            address = header.next_record;
+        }
+
+        self.shared_values = Some(shared_values);
+        Ok(())
+    }
+
+    fn find(&mut self, key_bytes: &[u8]) -> Result<Option<(RecordHeader, BlockAddress)>> {
+        self.find_from(self.system_info.first_record, key_bytes)
+    }
+
+    /// Finds the first record with `key_bytes` from the record at `start_address` on.
+    fn find_from(&mut self, start_address: BlockAddress, key_bytes: &[u8])
+        -> Result<Option<(RecordHeader, BlockAddress)>> {
+        let _span = self.tracer.span(Phase::Find);
+        let deadline_millis = self.deadline_millis
+            .or_else(|| self.operation_timeout.map(|timeout| self.clock.now_millis() + timeout.as_millis() as u64));
+        let mut walked = 0_u32;
+        let mut record_address = start_address;
+        while record_address != BlockAddress::invalid() {
+            if let Some(deadline_millis) = deadline_millis {
+                if walked.is_multiple_of(DEADLINE_CHECK_INTERVAL) && self.clock.now_millis() >= deadline_millis {
+                    return Err(Error::new(ErrorKind::TimedOut,
+                        format!("The deadline passed after walking {:?} records", walked)));
+                }
+            }
+
+            walked = walked.wrapping_add(1);
+            let mut reader = PageReader::new(&mut self.page_manager, record_address)?;
+            let record_header = read_linked_header(&mut reader, self.auth_key.as_deref())?;
+
+            let key_size = record_header.key_size as usize;
+            if key_size == key_bytes.len() {
+                if self.key_buffer.len() < key_size {
+                    self.key_buffer.resize(key_size, 0);
+                }
+
+                let key_slice = &mut self.key_buffer[0..key_size];
+                reader.read_exact(key_slice)?;
+
+                if key_slice.eq(&key_bytes) {
+                    return Ok(Some((record_header, record_address)));
+                }
+            }
+
+            record_address = record_header.next_record;
+        }
+
+        Ok(None)
+    }
+
+    /// Finds the record with `key_bytes` and the value `data`, for keys with several values.
+    fn find_value(&mut self, key_bytes: &[u8], data: &[u8]) -> Result<Option<(RecordHeader, BlockAddress)>> {
+        let mut value = self.take_buffer(data.len());
+        let mut start_address = self.system_info.first_record;
+        let mut found = None;
+        while let Some((header, address)) = self.find_from(start_address, key_bytes)? {
+            if header.data_size as usize == data.len() {
+                self.value_reader(&header, address)?.read_exact(&mut value)?;
+                if value == data {
+                    found = Some((header, address));
+                    break;
+                }
+            }
+
+            start_address = header.next_record;
+        }
+
+        self.give_back_buffer(value);
+        Ok(found)
+    }
+
+    fn read_system_info(&mut self) -> Result<()> {
+        self.system_info = self.file.borrow_mut().read_structure_from_pos(0)?;
+        Ok(())
+    }
 
-pub struct Database {
-    file: Rc<RefCell<File>>,
-    page_manager: PageManager,
-    system_info: DbSystemInfo,
-    key_buffer: Vec<u8>,
-}
+    fn authenticate_system_info(&self) -> Result<()> {
+        let signed = self.system_info.mac != [0; 32];
+        match &self.auth_key {
+            None if signed => Err(Error::new(ErrorKind::PermissionDenied, "The database is signed, open it with its key")),
+            None => Ok(()),
+            Some(_) if !signed => Err(Error::new(ErrorKind::InvalidData, "The database isn't signed")),
+            Some(key) => {
+                self.system_info.mac_state(key).verify_slice(&self.system_info.mac)
+                    .map_err(|_| Error::new(ErrorKind::InvalidData, "System info doesn't match its signature"))
+            },
+        }
+    }
 
-impl Database {
-    pub fn new(path: &str) -> Result<Self> {
-        let file = Rc::new(RefCell::new(
-            OpenOptions::new().create(true).read(true).write(true).open(path)?));
-        let page_manager = PageManager::new(file.clone(), DbSystemInfo::size_in_buffer() as u64)?;
-        let mut db = Database {
-            file: file.clone(),
-            page_manager,
-            system_info: DbSystemInfo::default(),
-            key_buffer: vec![0; 32],
-        };
-        if file.borrow().metadata()?.len() == 0 {
-            db.initialize()?;
+    /// With `auth_key`, walks the chain from `first_record` and checks every header against its MAC, the back links
+    /// and that it ends at `last_record`, so records modified, dropped or spliced in without the key fail the open.
+    fn authenticate_records(&mut self) -> Result<()> {
+        if self.auth_key.is_none() {
+            return Ok(());
         }
 
-        db.read_system_info()?;
+        let broken_chain = || Error::new(ErrorKind::InvalidData, "Record chain doesn't match its signature");
+        let max_records = self.page_manager.page_count()? as u64 * PAGE_BLOCK_COUNT as u64;
+        let mut prev_record = BlockAddress::invalid();
+        let mut address = self.system_info.first_record;
+        let mut records = 0;
+        while address != BlockAddress::invalid() {
+            if records >= max_records {
+                return Err(Error::new(ErrorKind::InvalidData, "Record chain has a cycle"));
+            }
 
-        Ok(db)
+            let header = self.read_record_header(address)?;
+            if header.prev_record != prev_record {
+                return Err(broken_chain());
+            }
+
+            prev_record = address;
+            address = header.next_record;
+            records += 1;
+        }
+
+        if prev_record != self.system_info.last_record {
+            return Err(broken_chain());
+        }
+
+        Ok(())
     }
 
-    fn initialize(&mut self) -> Result<()> {
-        self.system_info = DbSystemInfo::default();
+    fn write_system_info(&mut self) -> Result<()> {
+        // Records the pages don't hold anymore mustn't become reachable from the file.
+        self.page_manager.check_poisoned()?;
+
+        if let Some(key) = &self.auth_key {
+            self.system_info.mac = self.system_info.mac_state(key).finalize().into_bytes().into();
+        }
+
+        self.file.borrow_mut().write_structure_to_pos(0, &self.system_info)?;
+        self.system_info_dirty = false;
+        Ok(())
+    }
+
+    /// Clears `clean_shutdown` in the file before anything else is written, so a crash from now on is detected.
+    fn mark_in_use(&mut self) -> Result<()> {
+        self.system_info.clean_shutdown = 0;
         self.write_system_info()?;
+        if self.sync_mode != SyncMode::None {
+            self.sync()?;
+        }
+
+        self.marked_in_use = true;
         Ok(())
     }
 
-    pub fn set(&mut self, key: &str, data: &[u8]) {
-        let key_bytes = key.as_bytes();
-        if let Some(_) = self.find(key_bytes) {
-            return;
+    /// Follows the chain from the stored `last_record`, which can lag behind after a crash, to its real end.
+    /// Returns the number of records found past the stored one.
+    fn recover_last_record(&mut self) -> Result<u64> {
+        let mut last_record = self.system_info.last_record;
+        if last_record == BlockAddress::invalid() {
+            return Ok(0);
         }
 
-        let new_record_address = {
-            let mut page_writer = PageWriter::new(&mut self.page_manager).unwrap();
-            page_writer
-                .write_structure(&RecordHeader {
-                    next_record: BlockAddress::invalid(),
-                    key_size: key_bytes.len() as i32,
-                    data_size: data.len() as i32
-                })
-                .unwrap();
-
-            page_writer.write_all(key_bytes).unwrap();
-            page_writer.write_all(data).unwrap();
-            page_writer.start_address()
-        };
-
-        if self.system_info.last_record != BlockAddress::invalid() {
-            let mut page = self.page_manager.get_page(self.system_info.last_record.page_index).unwrap();
-            let block_index = self.system_info.last_record.block_index;
-            let header = page.get_block_data(block_index, 0, RecordHeader::size_in_buffer())
-                .read_structure::<RecordHeader>();
-            let mut buffer = [0 as u8; RecordHeader::size_in_buffer()];
-            buffer.write_structure(&RecordHeader { next_record: new_record_address, key_size: header.key_size, data_size: header.data_size });
-            page.set_block_data(block_index, &buffer, 0);
-        }
-
-        self.system_info.last_record = new_record_address;
-        if self.system_info.first_record == BlockAddress::invalid() {
-            self.system_info.first_record = new_record_address;
+        let max_records = self.page_manager.page_count()? as u64 * PAGE_BLOCK_COUNT as u64;
+        let mut recovered = 0;
+        loop {
+            let next_record = self.read_record_header(last_record)?.next_record;
+            if next_record == BlockAddress::invalid() {
+                break;
+            }
+
+            if recovered >= max_records {
+                return Err(Error::new(ErrorKind::InvalidData, "Record chain has a cycle"));
+            }
+
+            last_record = next_record;
+            recovered += 1;
+        }
+
+        if last_record != self.system_info.last_record {
+            self.system_info.last_record = last_record;
+            self.write_system_info()?;
         }
 
-        self.write_system_info().unwrap();
+        Ok(recovered)
+    }
+
+    /// Drops the database the way a crash would: without writing pending system info or syncing.
+    #[cfg(any(test, feature = "model_test"))]
+    pub(crate) fn simulate_crash(mut self) {
+        self.system_info_dirty = false;
+        self.has_unsynced_writes = false;
+        self.marked_in_use = false;
     }
+}
+
+/// Bytes of the blocks a record's own chain of `record_size` bytes takes.
+fn block_bytes(record_size: usize) -> u64 {
+    (record_size.div_ceil(read_write::BLOCK_DATA_SIZE).max(1) * BLOCK_SIZE) as u64
+}
+
+fn trash_key(key: &str) -> String {
+    format!("{}{}", TRASH_KEY_PREFIX, key)
+}
+
+#[cfg(windows)]
+const ERROR_SHARING_VIOLATION: i32 = 32;
+
+fn database_in_use() -> Error {
+    Error::new(ErrorKind::ResourceBusy, "The database is opened by another Database instance or process")
+}
+
+/// Takes the advisory lock that enforces a single writer per file. It's released when the file is closed.
+fn lock_exclusive(file: &File) -> Result<()> {
+    file.try_lock().map_err(|e| match e {
+        TryLockError::WouldBlock => database_in_use(),
+        TryLockError::Error(e) => e,
+    })
+}
 
-    pub fn get(&mut self, key: &str) -> Option<Vec<u8>> {
-        if let Some((header, address)) = self.find(key.as_bytes()) {
-            let mut reader = PageReader::new(&mut self.page_manager, address).unwrap();
-            reader.skip(RecordHeader::size_in_buffer() + header.key_size as usize).unwrap();
-            let mut result = vec![0; header.data_size as usize];
-            reader.read_exact(&mut result).unwrap();
-            Some(result)
+impl Drop for Database {
+    fn drop(&mut self) {
+        if self.system_info_dirty {
+            let _ = self.write_system_info();
         }
-        else {
-            None
+
+        let synced = !self.has_unsynced_writes || self.sync_mode == SyncMode::None || self.sync().is_ok();
+
+        // The flag is only written once everything it vouches for is.
+        if self.marked_in_use && synced && !self.system_info_dirty {
+            self.system_info.clean_shutdown = 1;
+            if self.write_system_info().is_ok() && self.sync_mode != SyncMode::None {
+                let _ = self.sync();
+            }
         }
     }
+}
 
-    pub fn get_to_buffer(&mut self, key: &str, buffer: &mut [u8]) -> bool {
-        if let Some((header, address)) = self.find(key.as_bytes()) {
-            if buffer.len() < header.data_size as usize {
-                panic!("123");
-            }
+pub struct Iter<'a> {
+    db: &'a mut Database,
+    next_record: BlockAddress,
+    next_back_record: BlockAddress,
+}
 
-            let mut reader = PageReader::new(&mut self.page_manager, address).unwrap();
-            reader.skip(RecordHeader::size_in_buffer() + header.key_size as usize).unwrap();
-            reader.read(buffer).unwrap();
-            true
+impl<'a> Iter<'a> {
+    /// Reads the record at `address` and moves the cursors past it. The cursors stop once they meet.
+    fn take(&mut self, address: BlockAddress, forward: bool) -> Option<Result<(String, Vec<u8>)>> {
+        if address == BlockAddress::invalid() {
+            return None;
         }
-        else {
-            false
+
+        let last = self.next_record == self.next_back_record;
+        match self.db.read_record(address) {
+            Ok((header, key, data)) => {
+                if last {
+                    self.next_record = BlockAddress::invalid();
+                    self.next_back_record = BlockAddress::invalid();
+                }
+                else if forward {
+                    self.next_record = header.next_record;
+                }
+                else {
+                    self.next_back_record = header.prev_record;
+                }
+
+                Some(Ok((key, data)))
+            },
+            Err(e) => {
+                self.next_record = BlockAddress::invalid();
+                self.next_back_record = BlockAddress::invalid();
+                Some(Err(e))
+            },
         }
     }
+}
 
-    fn find(&mut self, key_bytes: &[u8]) -> Option<(RecordHeader, BlockAddress)> {
-        if self.system_info.first_record == BlockAddress::invalid() {
+impl<'a> Iterator for Iter<'a> {
+    type Item = Result<(String, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.take(self.next_record, true)
+    }
+}
+
+impl<'a> DoubleEndedIterator for Iter<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.take(self.next_back_record, false)
+    }
+}
+
+pub struct ModifiedSince<'a> {
+    db: &'a mut Database,
+    next_record: BlockAddress,
+    since_millis: u64,
+}
+
+impl<'a> Iterator for ModifiedSince<'a> {
+    type Item = Result<(String, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.next_record != BlockAddress::invalid() {
+            let address = self.next_record;
+            let header = match self.db.read_record_header(address) {
+                Ok(header) => header,
+                Err(error) => {
+                    self.next_record = BlockAddress::invalid();
+                    return Some(Err(error));
+                },
+            };
+
+            self.next_record = header.next_record;
+            if header.updated_millis >= self.since_millis {
+                return Some(self.db.read_record(address).map(|(_, key, value)| (key, value)));
+            }
+        }
+
+        None
+    }
+}
+
+pub struct CursorIter<'a> {
+    db: &'a mut Database,
+    next_record: BlockAddress,
+    cursor: RecordCursor,
+}
+
+impl<'a> CursorIter<'a> {
+    /// The position after the records returned so far.
+    pub fn cursor(&self) -> RecordCursor {
+        self.cursor.clone()
+    }
+}
+
+impl<'a> Iterator for CursorIter<'a> {
+    type Item = Result<(String, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next_record == BlockAddress::invalid() {
             return None;
         }
 
-        let mut record_address = self.system_info.first_record;
-        while record_address != BlockAddress::invalid() {
-            let mut reader = PageReader::new(&mut self.page_manager, record_address).unwrap();
-            let record_header = reader.read_structure::<RecordHeader>().unwrap();
+        let address = self.next_record;
+        match self.db.read_record(address) {
+            Ok((header, key, value)) => {
+                self.next_record = header.next_record;
+                self.cursor.last = Some(CursorRecord { address, key: key.clone(), created_millis: header.created_millis });
+                Some(Ok((key, value)))
+            },
+            Err(error) => {
+                self.next_record = BlockAddress::invalid();
+                Some(Err(error))
+            },
+        }
+    }
+}
 
-            let key_size = record_header.key_size as usize;
-            if key_size == key_bytes.len() {
-                if self.key_buffer.len() < key_size {
-                    self.key_buffer.resize(key_size, 0);
-                }
+pub struct PhysicalIter<'a> {
+    db: &'a mut Database,
+    records: VecDeque<BlockAddress>,
+}
 
-                let key_slice = &mut self.key_buffer[0..key_size];
-                reader.read_exact(key_slice).unwrap();
+impl<'a> Iterator for PhysicalIter<'a> {
+    type Item = Result<(String, Vec<u8>)>;
 
-                if key_slice.eq(&key_bytes) {
-                    return Some((record_header, record_address));
-                }
+    fn next(&mut self) -> Option<Self::Item> {
+        let address = self.records.pop_front()?;
+        Some(self.db.read_record(address).map(|(_, key, value)| (key, value)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.records.len(), Some(self.records.len()))
+    }
+}
+
+pub struct VerifyChain<'a> {
+    db: &'a mut Database,
+    next_record: BlockAddress,
+    prev_record: BlockAddress,
+    /// Records after the first damaged one, in chain order, once they have been collected from the end of the chain.
+    salvaged: Option<VecDeque<std::result::Result<BlockAddress, CorruptionReport>>>,
+}
+
+impl<'a> VerifyChain<'a> {
+    /// Follows `prev_record` from the last record back to `damaged_record` or to the next damaged header.
+    fn salvage(&mut self, damaged_record: BlockAddress) -> VecDeque<std::result::Result<BlockAddress, CorruptionReport>> {
+        let mut salvaged = VecDeque::new();
+        let mut visited = HashSet::new();
+        let mut address = self.db.system_info.last_record;
+        while address != BlockAddress::invalid() && address != damaged_record && visited.insert(address) {
+            match self.db.probe_record_header(address) {
+                Ok(header) => {
+                    salvaged.push_front(Ok(address));
+                    address = header.prev_record;
+                },
+                Err(error) => {
+                    salvaged.push_front(Err(CorruptionReport { address, error }));
+                    break;
+                },
             }
+        }
 
-            record_address = record_header.next_record;
+        salvaged
+    }
+}
+
+impl<'a> Iterator for VerifyChain<'a> {
+    type Item = std::result::Result<(String, RecordMeta), CorruptionReport>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(salvaged) = &mut self.salvaged {
+            return match salvaged.pop_front()? {
+                Ok(address) => Some(self.db.read_record(address)
+                    .map(|(header, key, _)| (key, RecordMeta::from(&header)))
+                    .map_err(|error| CorruptionReport { address, error })),
+                Err(report) => Some(Err(report)),
+            };
         }
 
-        None
+        let address = self.next_record;
+        if address == BlockAddress::invalid() {
+            return None;
+        }
+
+        match self.db.verify_record(address, self.prev_record) {
+            Ok((header, key)) => {
+                self.prev_record = address;
+                self.next_record = header.next_record;
+                Some(Ok((key, RecordMeta::from(&header))))
+            },
+            Err(error) => {
+                self.salvaged = Some(self.salvage(address));
+                Some(Err(CorruptionReport { address, error }))
+            },
+        }
     }
+}
 
-    fn read_system_info(&mut self) -> Result<()> {
-        self.system_info = self.file.borrow_mut().read_structure_from_pos(0)?;
-        Ok(())
+/// A record `verify_chain` couldn't read or link into the chain.
+#[derive(Debug)]
+pub struct CorruptionReport {
+    pub address: BlockAddress,
+    pub error: Error,
+}
+
+impl Display for CorruptionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Record at {} is damaged: {}", self.address, self.error)
     }
+}
 
-    fn write_system_info(&mut self) -> Result<()> {
-        self.file.borrow_mut().write_structure_to_pos(0, &self.system_info)?;
-        Ok(())
+impl std::error::Error for CorruptionReport {}
+
+/// Memory used by the page cache. Page changes are written to the file immediately, so all cached pages are clean
+/// and can be evicted at any time.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CacheStats {
+    pub cached_pages: usize,
+    pub cached_bytes: usize,
+}
+
+/// Information about a record that doesn't require reading its value.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RecordMeta {
+    pub key_size: usize,
+    pub value_size: usize,
+    /// Milliseconds since the Unix epoch when the record was written.
+    pub created_millis: u64,
+    /// Milliseconds since the Unix epoch when the value was last modified.
+    pub updated_millis: u64,
+}
+
+impl From<&RecordHeader> for RecordMeta {
+    fn from(header: &RecordHeader) -> Self {
+        RecordMeta {
+            key_size: header.key_size as usize,
+            value_size: header.data_size as usize,
+            created_millis: header.created_millis,
+            updated_millis: header.updated_millis,
+        }
     }
 }
 
 #[derive(Default, Clone)]
+#[repr(C)]
 struct DbSystemInfo {
+    magic: [u8; 4],
+    format_version: u32,
     first_record: BlockAddress,
     last_record: BlockAddress,
+    /// 1 while the file is closed after a clean shutdown, 0 while a `Database` has it open, so a crash leaves 0.
+    clean_shutdown: u32,
+    /// HMAC-SHA256 of the other fields with `Options::auth_key`, zeros if the file has no key.
+    mac: [u8; 32],
+}
+
+impl DbSystemInfo {
+    /// HMAC state fed with every field except `mac`, field by field so padding bytes don't count.
+    fn mac_state(&self, key: &[u8]) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any size");
+        let fields: [&[u8]; 7] = [
+            &self.magic, &self.format_version.to_le_bytes(),
+            &self.first_record.page_index.to_le_bytes(), &[self.first_record.block_index],
+            &self.last_record.page_index.to_le_bytes(), &[self.last_record.block_index],
+            &self.clean_shutdown.to_le_bytes(),
+        ];
+        fields.iter().for_each(|f| mac.update(f));
+        mac
+    }
 }
 
 impl ReadableWritable for DbSystemInfo {
@@ -163,20 +2562,118 @@ impl ReadableWritable for DbSystemInfo {
     }
 }
 
+/// The value isn't stored after the key; a `BlockAddress` of a shared value chain is stored instead.
+const RECORD_FLAG_SHARED_VALUE: u32 = 1;
+
 #[derive(Clone)]
+#[repr(C)]
 struct RecordHeader {
     next_record: BlockAddress,
+    /// Previous record in insertion order, so deletes and reverse iteration don't walk the chain from the start.
+    prev_record: BlockAddress,
     key_size: i32,
     data_size: i32,
+    flags: u32,
+    /// CRC-32C of the other fields, or with `Options::auth_key` the start of their HMAC-SHA256, set when the header
+    /// is written. A mismatch on read means the header is damaged or was modified without the key, so none of its
+    /// fields, in particular the links, can be trusted.
+    checksum: u32,
+    /// Milliseconds since the Unix epoch, taken from `Options::clock`.
+    created_millis: u64,
+    updated_millis: u64,
+}
+
+/// The error payload of a record header that fails its checksum.
+#[derive(Debug)]
+struct ChecksumMismatch;
+
+impl Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Record header is corrupted (checksum mismatch)")
+    }
+}
+
+impl std::error::Error for ChecksumMismatch {}
+
+/// Poisons the database if `error` is a record header checksum mismatch, see `Database::read_record_header`.
+fn contain_corruption(page_manager: &mut PageManager, error: &Error) {
+    if error.get_ref().is_some_and(|e| e.is::<ChecksumMismatch>()) {
+        page_manager.poison(error.to_string());
+    }
+}
+
+/// Reads the header of the record `reader` starts at, poisoning the database like `Database::read_record_header`.
+fn read_linked_header(reader: &mut PageReader, auth_key: Option<&[u8]>) -> Result<RecordHeader> {
+    reader.read_structure::<RecordHeader>()
+        .and_then(|header| header.checked(auth_key))
+        .inspect_err(|error| contain_corruption(reader.page_manager(), error))
 }
 
 impl RecordHeader {
     const fn size_in_buffer() -> usize {
         size_of::<RecordHeader>()
     }
+
+    fn compute_checksum(&self, auth_key: Option<&[u8]>) -> u32 {
+        let fields: [&[u8]; 9] = [
+            &self.next_record.page_index.to_le_bytes(), &[self.next_record.block_index],
+            &self.prev_record.page_index.to_le_bytes(), &[self.prev_record.block_index],
+            &self.key_size.to_le_bytes(), &self.data_size.to_le_bytes(), &self.flags.to_le_bytes(),
+            &self.created_millis.to_le_bytes(), &self.updated_millis.to_le_bytes(),
+        ];
+        let mut bytes = [0; 41];
+        let mut length = 0;
+        for field in fields {
+            bytes[length..length + field.len()].copy_from_slice(field);
+            length += field.len();
+        }
+
+        match auth_key {
+            None => checksum::crc32c(&bytes[..length]),
+            Some(key) => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any size");
+                mac.update(&bytes[..length]);
+                let tag = mac.finalize().into_bytes();
+                u32::from_le_bytes([tag[0], tag[1], tag[2], tag[3]])
+            },
+        }
+    }
+
+    /// The header with its checksum set, as it's written.
+    fn sealed(&self, auth_key: Option<&[u8]>) -> RecordHeader {
+        RecordHeader { checksum: self.compute_checksum(auth_key), ..self.clone() }
+    }
+
+    /// Fails with a checksum mismatch if the header doesn't match its checksum.
+    fn checked(self, auth_key: Option<&[u8]>) -> Result<RecordHeader> {
+        if self.checksum != self.compute_checksum(auth_key) {
+            return Err(Error::new(ErrorKind::InvalidData, ChecksumMismatch));
+        }
+
+        Ok(self)
+    }
+
+    /// Number of bytes of the record's own chain: header, key and stored value.
+    fn record_size(&self) -> usize {
+        RecordHeader::size_in_buffer() + self.key_size as usize + self.stored_value_size()
+    }
+
+    /// Number of bytes after the key: the value, or the reference to a shared value.
+    fn stored_value_size(&self) -> usize {
+        if self.flags & RECORD_FLAG_SHARED_VALUE != 0 {
+            BlockAddress::size_in_buffer()
+        }
+        else {
+            self.data_size as usize
+        }
+    }
 }
 
+// `read_record_header` and `write_record_header` rely on the header fitting into the first block.
+const _: () = assert!(RecordHeader::size_in_buffer() <= read_write::BLOCK_DATA_SIZE);
+
 impl ReadableWritable for RecordHeader {
+    /// Reads the header as stored, see `RecordHeader::checked`.
     fn read_to_buffer(read_action: impl FnOnce(&mut [u8]) -> Result<Self>) -> Result<Self> {
         let mut buffer = [0; size_of::<Self>()];
         read_action(&mut buffer)
@@ -200,4 +2697,203 @@ impl ReadableWritable for RecordHeader {
 
 //         Ok(())
 //     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::{self, Cursor, ErrorKind, Read, Seek, SeekFrom, Write}, path::PathBuf, rc::Rc, cell::RefCell};
+
+    use tempfile::TempDir;
+
+    use super::{BlockAddress, Database, DbSystemInfo, Options, RecordHeader, Storage, utils::ReadStructurePos};
+
+    /// In-memory storage that can't grow past `capacity` bytes, like a disk that runs full. Clones share the bytes,
+    /// so a database can be reopened on what another one wrote.
+    #[derive(Clone)]
+    struct LimitedStorage {
+        bytes: Rc<RefCell<Cursor<Vec<u8>>>>,
+        capacity: Rc<RefCell<u64>>,
+    }
+
+    impl LimitedStorage {
+        fn new() -> Self {
+            LimitedStorage { bytes: Rc::default(), capacity: Rc::new(RefCell::new(u64::MAX)) }
+        }
+
+        fn check_capacity(&self, size: u64) -> io::Result<()> {
+            if size > *self.capacity.borrow() {
+                return Err(io::Error::new(ErrorKind::StorageFull, "No space left on the device"));
+            }
+
+            Ok(())
+        }
+    }
+
+    impl Read for LimitedStorage {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.bytes.borrow_mut().read(buf)
+        }
+    }
+
+    impl Write for LimitedStorage {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.check_capacity(self.bytes.borrow().position() + buf.len() as u64)?;
+            self.bytes.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for LimitedStorage {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.bytes.borrow_mut().seek(pos)
+        }
+    }
+
+    impl Storage for LimitedStorage {
+        fn size(&self) -> io::Result<u64> {
+            self.bytes.borrow().size()
+        }
+
+        fn set_size(&mut self, size: u64) -> io::Result<()> {
+            if size > self.bytes.borrow().size()? {
+                self.check_capacity(size)?;
+            }
+
+            self.bytes.borrow_mut().set_size(size)
+        }
+
+        fn sync_data(&self) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn sync_all(&self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn assert_records(db: &mut Database, count: usize) {
+        for i in 0..count {
+            assert_eq!(db.get(&format!("key{}", i)).unwrap(), Some(vec![i as u8; 100]));
+        }
+        assert_eq!(db.verify().unwrap(), count);
+        assert_eq!(db.gc_dry_run().unwrap().bytes, 0);
+    }
+
+    fn db_path() -> (TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.db");
+        (dir, path)
+    }
+
+    #[test]
+    fn set_on_full_storage_leaves_database_as_it_was() {
+        let storage = LimitedStorage::new();
+        let mut db = Database::from_storage(storage.clone(), Options::default()).unwrap();
+        for i in 0..50 {
+            db.set(&format!("key{}", i), &[i as u8; 100]).unwrap();
+        }
+
+        // Room for part of the value, so the set fails after taking some blocks.
+        *storage.capacity.borrow_mut() = db.file.borrow().size().unwrap() + 4096;
+        let error = db.set("big", &[7; 64 * 1024]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::StorageFull);
+        assert_eq!(db.get("big").unwrap(), None);
+        assert_records(&mut db, 50);
+        assert_eq!(db.gc().unwrap(), 0);
+
+        db.set("key50", &[50; 100]).unwrap();
+        drop(db);
+
+        let mut db = Database::from_storage(storage, Options::default()).unwrap();
+        assert!(db.opened_after_clean_shutdown());
+        assert_records(&mut db, 51);
+    }
+
+    #[test]
+    fn put_on_full_storage_keeps_old_value() {
+        let storage = LimitedStorage::new();
+        let mut db = Database::from_storage(storage.clone(), Options::default()).unwrap();
+        for i in 0..50 {
+            db.set(&format!("key{}", i), &[i as u8; 100]).unwrap();
+        }
+
+        *storage.capacity.borrow_mut() = db.file.borrow().size().unwrap() + 4096;
+        let error = db.put("key7", &[7; 64 * 1024]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::StorageFull);
+        assert_records(&mut db, 50);
+
+        db.put("key7", &[7; 100]).unwrap();
+        assert_records(&mut db, 50);
+        let keys: Vec<String> = db.iter().map(|record| record.unwrap().0).collect();
+        assert_eq!(keys.last().unwrap(), "key7");
+    }
+
+    #[test]
+    fn recovers_records_appended_before_crash() {
+        let (_dir, path) = db_path();
+        let mut db = Database::new(&path).unwrap();
+        for key in ["a", "b", "c"] {
+            db.set(key, key.as_bytes()).unwrap();
+        }
+        // Only the first record's append writes the system info, the others leave `last_record` behind.
+        let stored_last_record = db.file.borrow_mut().read_structure_from_pos::<DbSystemInfo>(0).unwrap().last_record;
+        assert_ne!(stored_last_record, db.system_info.last_record);
+        db.simulate_crash();
+
+        let mut db = Database::new(&path).unwrap();
+        assert!(!db.opened_after_clean_shutdown());
+        assert_eq!(db.last().unwrap(), Some(("c".to_string(), b"c".to_vec())));
+        let keys: Vec<String> = db.iter().rev().map(|record| record.unwrap().0).collect();
+        assert_eq!(keys, ["c", "b", "a"]);
+        assert_eq!(db.verify().unwrap(), 3);
+
+        db.set("d", b"d").unwrap();
+        let keys: Vec<String> = db.iter().map(|record| record.unwrap().0).collect();
+        assert_eq!(keys, ["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn recovery_stops_at_cycle() {
+        let (_dir, path) = db_path();
+        let mut db = Database::new(&path).unwrap();
+        db.set("a", b"a").unwrap();
+        db.set("b", b"b").unwrap();
+        let DbSystemInfo { first_record, last_record, .. } = db.system_info;
+        let header = db.read_record_header(last_record).unwrap();
+        db.write_record_header(last_record, &RecordHeader { next_record: first_record, ..header }).unwrap();
+        assert_ne!(first_record, BlockAddress::invalid());
+        db.simulate_crash();
+
+        let error = Database::new(&path).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(error.to_string().contains("cycle"), "{}", error);
+    }
+
+    #[test]
+    fn signed_database_rejects_modified_record_header() {
+        let (_dir, path) = db_path();
+        let options = || Options { auth_key: Some(b"secret".to_vec()), ..Options::default() };
+        let mut db = Database::with_options(&path, options()).unwrap();
+        for key in ["a", "b", "c"] {
+            db.set(key, key.as_bytes()).unwrap();
+        }
+        db.delete("b").unwrap();
+        drop(db);
+
+        let mut db = Database::with_options(&path, options()).unwrap();
+        assert_eq!(db.verify().unwrap(), 2);
+
+        // A header that is only consistent with its CRC-32C, like one rewritten without the key.
+        let address = db.system_info.first_record;
+        let header = db.read_record_header(address).unwrap();
+        db.auth_key = None;
+        db.write_record_header(address, &RecordHeader { updated_millis: header.updated_millis + 1, ..header }).unwrap();
+        db.simulate_crash();
+
+        let error = Database::with_options(&path, options()).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
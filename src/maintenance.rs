@@ -0,0 +1,99 @@
+use std::io::{Result, Error, ErrorKind};
+
+use serde_json::{Value, json};
+
+/// Maintenance events are stored as records under this prefix followed by the event's sequence number as 16 hex
+/// digits, like audit entries.
+pub const MAINTENANCE_KEY_PREFIX: &str = "maintenance/";
+
+/// The operation a `MaintenanceEvent` records.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MaintenanceOp {
+    /// Opening a file that wasn't closed cleanly repaired the end of the record chain.
+    Recovery,
+    Reopen,
+    Verify,
+    /// `defragment_all`.
+    Defragment,
+    Gc,
+    PurgeTrash,
+}
+
+impl MaintenanceOp {
+    fn name(self) -> &'static str {
+        match self {
+            MaintenanceOp::Recovery => "recovery",
+            MaintenanceOp::Reopen => "reopen",
+            MaintenanceOp::Verify => "verify",
+            MaintenanceOp::Defragment => "defragment",
+            MaintenanceOp::Gc => "gc",
+            MaintenanceOp::PurgeTrash => "purge_trash",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [MaintenanceOp::Recovery, MaintenanceOp::Reopen, MaintenanceOp::Verify, MaintenanceOp::Defragment,
+            MaintenanceOp::Gc, MaintenanceOp::PurgeTrash]
+            .into_iter()
+            .find(|op| op.name() == name)
+    }
+}
+
+/// One maintenance operation recorded with `Options::maintenance_log`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct MaintenanceEvent {
+    pub sequence: u64,
+    /// When the operation finished.
+    pub millis: u64,
+    pub duration_millis: u64,
+    pub op: MaintenanceOp,
+    /// Records the operation checked (`Verify`), rewrote (`Defragment`), removed (`PurgeTrash`) or found past the
+    /// stored end of the chain (`Recovery`).
+    pub records: u64,
+    /// Bytes `Gc` reclaimed.
+    pub bytes: u64,
+    /// The error the operation failed with.
+    pub error: Option<String>,
+}
+
+impl MaintenanceEvent {
+    pub(crate) fn record_key(&self) -> String {
+        format!("{}{:016x}", MAINTENANCE_KEY_PREFIX, self.sequence)
+    }
+
+    /// The value of the event's record, a JSON object.
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut object = json!({ "millis": self.millis, "duration_millis": self.duration_millis, "op": self.op.name(),
+            "records": self.records, "bytes": self.bytes });
+        if let Some(error) = &self.error {
+            object["error"] = Value::String(error.clone());
+        }
+
+        object.to_string().into_bytes()
+    }
+
+    pub(crate) fn decode(record_key: &str, value: &[u8]) -> Result<Self> {
+        let invalid = || Error::new(ErrorKind::InvalidData, format!("Invalid maintenance event {:?}", record_key));
+        let object: Value = serde_json::from_slice(value).map_err(|_| invalid())?;
+        let number = |name: &str| object.get(name).and_then(Value::as_u64).ok_or_else(invalid);
+        Ok(MaintenanceEvent {
+            sequence: parse_sequence(record_key).ok_or_else(invalid)?,
+            millis: number("millis")?,
+            duration_millis: number("duration_millis")?,
+            op: object.get("op").and_then(Value::as_str).and_then(MaintenanceOp::from_name).ok_or_else(invalid)?,
+            records: number("records")?,
+            bytes: number("bytes")?,
+            error: object.get("error").and_then(Value::as_str).map(str::to_string),
+        })
+    }
+}
+
+/// The sequence number of the event with `record_key`, or `None` if it isn't a maintenance event's key.
+pub(crate) fn parse_sequence(record_key: &str) -> Option<u64> {
+    u64::from_str_radix(record_key.strip_prefix(MAINTENANCE_KEY_PREFIX)?, 16).ok()
+}
+
+/// State of an open database with `Options::maintenance_log`.
+pub(crate) struct MaintenanceLog {
+    pub next_sequence: u64,
+}
@@ -0,0 +1,33 @@
+use sha2::{Digest, Sha256};
+
+/// Hash of one record. The lengths keep `("ab", "c")` and `("a", "bc")` apart.
+pub fn leaf_hash(key: &str, value: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update((key.len() as u64).to_le_bytes());
+    hasher.update(key.as_bytes());
+    hasher.update((value.len() as u64).to_le_bytes());
+    hasher.update(value);
+    hasher.finalize().into()
+}
+
+/// Hashes of the ranges between consecutive `boundaries`: range `i` holds the keys in
+/// `boundaries[i - 1]..boundaries[i]`, the first one starts at the smallest key and the last one ends after the
+/// largest, so there is one range more than there are boundaries. `leaves` must be sorted by key and
+/// `boundaries` ascending.
+pub fn range_hashes(leaves: &[(String, [u8; 32])], boundaries: &[&str]) -> Vec<[u8; 32]> {
+    let mut hashes = Vec::with_capacity(boundaries.len() + 1);
+    let mut hasher = Sha256::new();
+    let mut boundary = 0;
+    for (key, leaf) in leaves {
+        while boundary < boundaries.len() && key.as_str() >= boundaries[boundary] {
+            hashes.push(hasher.finalize_reset().into());
+            boundary += 1;
+        }
+
+        hasher.update(leaf);
+    }
+
+    hashes.push(hasher.finalize_reset().into());
+    hashes.resize(boundaries.len() + 1, Sha256::digest([]).into());
+    hashes
+}
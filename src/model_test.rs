@@ -0,0 +1,282 @@
+use std::{collections::BTreeMap, fmt::{self, Display}, fs, io, path::Path};
+
+use crate::{Database, Options, determinism::DeterministicRng};
+
+/// Parameters of a randomized run comparing `Database` against an in-memory `BTreeMap` model.
+#[derive(Clone, Debug)]
+pub struct ModelTestConfig {
+    pub seed: u64,
+    pub operations: usize,
+    /// Keys are drawn from `key0..key{key_space}`, so smaller spaces exercise more collisions.
+    pub key_space: u64,
+    pub max_value_size: usize,
+    /// Chance of closing and reopening the database (or just dropping its page cache) before an operation, in percent.
+    pub reopen_percent: u64,
+}
+
+impl Default for ModelTestConfig {
+    fn default() -> Self {
+        ModelTestConfig { seed: 0, operations: 1000, key_space: 100, max_value_size: 300, reopen_percent: 2 }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Operation {
+    Set { key: String, value: Vec<u8> },
+    Get { key: String },
+    GetToBuffer { key: String },
+    GetInto { key: String },
+    WriteAt { key: String, offset: usize, data: Vec<u8> },
+    ReadAt { key: String, offset: usize, length: usize },
+    Delete { key: String },
+    Rename { key: String, new_key: String },
+    DeletePrefix { prefix: String },
+    Clear,
+    DefragmentAll,
+    /// Collects garbage, which must find nothing: every operation frees the blocks it stops using.
+    Gc,
+    Verify,
+    /// Keeps only the records with values of at most `max_value_size` bytes.
+    Retain { max_value_size: usize },
+    Iterate,
+    IterateBackwards,
+    Reopen,
+    /// Reopens the database after dropping it without the writes and syncs of a normal close.
+    Crash,
+    ShrinkCache,
+}
+
+#[derive(Debug)]
+pub enum ModelTestError {
+    Io(io::Error),
+    Mismatch { step: usize, operation: Operation, message: String },
+}
+
+impl Display for ModelTestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModelTestError::Io(e) => write!(f, "I/O error: {}", e),
+            ModelTestError::Mismatch { step, operation, message } =>
+                write!(f, "Step {:?} ({:?}) diverged from the model: {}", step, operation, message),
+        }
+    }
+}
+
+impl From<io::Error> for ModelTestError {
+    fn from(e: io::Error) -> Self {
+        ModelTestError::Io(e)
+    }
+}
+
+/// Applies `config.operations` random operations to a fresh database at `path` and to the model,
+/// failing on the first observable difference. The same config always produces the same run.
+pub fn run(path: &str, config: &ModelTestConfig) -> Result<(), ModelTestError> {
+    if Path::new(path).exists() {
+        fs::remove_file(path)?;
+    }
+
+    let mut rng = DeterministicRng::new(config.seed);
+    let mut model = BTreeMap::new();
+    let mut db = Database::with_options(path, Options::deterministic(config.seed))?;
+
+    for step in 0..config.operations {
+        let operation = next_operation(&mut rng, config);
+        let mismatch = |message: String| ModelTestError::Mismatch { step, operation: operation.clone(), message };
+
+        match &operation {
+            Operation::Set { key, value } => {
+                db.set(key, value)?;
+                model.entry(key.clone()).or_insert_with(|| value.clone());
+            },
+            Operation::Get { key } => {
+                let actual = db.get(key)?;
+                if actual.as_ref() != model.get(key) {
+                    return Err(mismatch(format!("expected {:?}, got {:?}", model.get(key), actual)));
+                }
+            },
+            Operation::GetToBuffer { key } => {
+                let mut buffer = vec![0; config.max_value_size];
+                let found = db.get_to_buffer(key, &mut buffer)?;
+                match model.get(key) {
+                    Some(value) if !found || buffer[..value.len()] != value[..] =>
+                        return Err(mismatch(format!("expected {:?}", value))),
+                    None if found => return Err(mismatch("found a missing key".to_string())),
+                    _ => {},
+                }
+            },
+            Operation::GetInto { key } => {
+                let mut out = vec![1, 2, 3];
+                let found = db.get_into(key, &mut out)?;
+                let expected = model.get(key);
+                if found != expected.is_some() || out != expected.cloned().unwrap_or_default() {
+                    return Err(mismatch(format!("expected {:?}, got {:?}", expected, out)));
+                }
+            },
+            Operation::WriteAt { key, offset, data } => {
+                let fits = model.get(key).map(|v: &Vec<u8>| offset + data.len() <= v.len());
+                match (db.write_at(key, *offset, data), fits) {
+                    (Ok(false), None) | (Err(_), Some(false)) => {},
+                    (Ok(true), Some(true)) => {
+                        model.get_mut(key).unwrap()[*offset..*offset + data.len()].copy_from_slice(data);
+                    },
+                    (result, _) => return Err(mismatch(format!("unexpected result {:?}", result))),
+                }
+            },
+            Operation::ReadAt { key, offset, length } => {
+                let mut buffer = vec![0; *length];
+                let result = db.read_at(key, *offset, &mut buffer);
+                let expected = model.get(key).map(|v: &Vec<u8>| v.get(*offset..).map(|tail| &tail[..tail.len().min(*length)]));
+                match (result, expected) {
+                    (Ok(None), None) | (Err(_), Some(None)) => {},
+                    (Ok(Some(read)), Some(Some(tail))) if buffer[..read] == *tail => {},
+                    (result, expected) => return Err(mismatch(format!("expected {:?}, got {:?}", expected, result))),
+                }
+            },
+            Operation::Delete { key } => {
+                let deleted = db.delete(key)?;
+                if deleted != model.remove(key).is_some() {
+                    return Err(mismatch(format!("delete returned {:?}", deleted)));
+                }
+            },
+            Operation::Rename { key, new_key } => {
+                let result = db.rename(key, new_key);
+                match (result, model.contains_key(key), model.contains_key(new_key)) {
+                    (Ok(false), false, _) => {},
+                    (Ok(true), true, _) if key == new_key => {},
+                    (Err(_), true, true) => {},
+                    (Ok(true), true, false) => {
+                        let value = model.remove(key).unwrap();
+                        model.insert(new_key.clone(), value);
+                    },
+                    (result, _, _) => return Err(mismatch(format!("unexpected result {:?}", result))),
+                }
+            },
+            Operation::DeletePrefix { prefix } => {
+                let removed = db.delete_prefix(prefix)?;
+                let before = model.len();
+                model.retain(|k: &String, _| !k.starts_with(prefix.as_str()));
+                if removed != before - model.len() {
+                    return Err(mismatch(format!("expected {:?} removed records, got {:?}", before - model.len(), removed)));
+                }
+            },
+            Operation::Retain { max_value_size } => {
+                let removed = db.retain(|_, meta| meta.value_size <= *max_value_size)?;
+                let before = model.len();
+                model.retain(|_, v: &mut Vec<u8>| v.len() <= *max_value_size);
+                if removed != before - model.len() {
+                    return Err(mismatch(format!("expected {:?} removed records, got {:?}", before - model.len(), removed)));
+                }
+            },
+            Operation::DefragmentAll => {
+                db.defragment_all()?;
+            },
+            Operation::Gc => {
+                let freed = db.gc()?;
+                if freed != 0 {
+                    return Err(mismatch(format!("{:?} bytes were leaked", freed)));
+                }
+            },
+            Operation::Verify => {
+                let count = db.verify()?;
+                if count != model.len() {
+                    return Err(mismatch(format!("expected {:?} records, verified {:?}", model.len(), count)));
+                }
+            },
+            Operation::Clear => {
+                db.clear()?;
+                model.clear();
+            },
+            Operation::Iterate => {
+                let mut records = db.iter().collect::<io::Result<Vec<_>>>()?;
+                records.sort();
+                let expected: Vec<_> = model.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+                if records != expected {
+                    return Err(mismatch(format!("expected {:?} records, got {:?}", expected.len(), records.len())));
+                }
+            },
+            Operation::IterateBackwards => {
+                let forward = db.iter().collect::<io::Result<Vec<_>>>()?;
+                let mut backward = db.iter().rev().collect::<io::Result<Vec<_>>>()?;
+                backward.reverse();
+                if forward != backward {
+                    return Err(mismatch("forward and backward iteration differ".to_string()));
+                }
+            },
+            Operation::ShrinkCache => {
+                db.shrink_cache(0);
+            },
+            Operation::Crash => {
+                db.simulate_crash();
+                db = Database::with_options(path, Options::deterministic(config.seed))?;
+            },
+            Operation::Reopen => {
+                drop(db);
+                db = Database::with_options(path, Options::deterministic(config.seed))?;
+            },
+        }
+    }
+
+    Ok(())
+}
+
+fn next_operation(rng: &mut DeterministicRng, config: &ModelTestConfig) -> Operation {
+    if rng.next_below(100) < config.reopen_percent {
+        return match rng.next_below(3) {
+            0 => Operation::Reopen,
+            1 => Operation::Crash,
+            _ => Operation::ShrinkCache,
+        };
+    }
+
+    let key = format!("key{}", rng.next_below(config.key_space.max(1)));
+    match rng.next_below(14) {
+        0..=3 => Operation::Set { key, value: random_bytes(rng, config.max_value_size) },
+        4..=6 => Operation::Get { key },
+        7 => Operation::GetToBuffer { key },
+        8 => Operation::GetInto { key },
+        9 => {
+            let offset = rng.next_below(config.max_value_size as u64 + 1) as usize;
+            Operation::WriteAt { key, offset, data: random_bytes(rng, config.max_value_size / 4) }
+        },
+        10 => {
+            let offset = rng.next_below(config.max_value_size as u64 + 1) as usize;
+            Operation::ReadAt { key, offset, length: rng.next_below(config.max_value_size as u64 / 4 + 1) as usize }
+        },
+        11 => match rng.next_below(20) {
+            0 => Operation::Clear,
+            1 => Operation::Retain { max_value_size: rng.next_below(config.max_value_size as u64 + 1) as usize },
+            2..=3 => Operation::DeletePrefix { prefix: key[..key.len() - 1].to_string() },
+            4 => match rng.next_below(3) {
+                0 => Operation::DefragmentAll,
+                1 => Operation::Gc,
+                _ => Operation::Verify,
+            },
+            5..=7 => Operation::Rename { key, new_key: format!("key{}", rng.next_below(config.key_space.max(1) * 10)) },
+            _ => Operation::Delete { key },
+        },
+        12 => Operation::IterateBackwards,
+        _ => Operation::Iterate,
+    }
+}
+
+fn random_bytes(rng: &mut DeterministicRng, max_size: usize) -> Vec<u8> {
+    let size = rng.next_below(max_size as u64 + 1) as usize;
+    (0..size).map(|_| rng.next_u64() as u8).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ModelTestConfig, run};
+
+    #[test]
+    fn matches_model() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("model.db");
+        for seed in [1, 2, 3, 42, 1337] {
+            let config = ModelTestConfig { seed, operations: 2000, ..ModelTestConfig::default() };
+            if let Err(e) = run(path.to_str().unwrap(), &config) {
+                panic!("Seed {}: {}", seed, e);
+            }
+        }
+    }
+}
@@ -1,8 +1,9 @@
-use std::{ops::Range, io::{Result, Seek, Error, ErrorKind}, fs::File, collections::HashMap, cell::{RefCell, Ref}, rc::Rc, fmt::{Display}, mem::size_of};
+use std::{ops::Range, io::{Result, Seek, Error, ErrorKind}, collections::HashMap, cell::{Cell, RefCell, Ref, RefMut}, rc::Rc, fmt::{Debug, Display}, hash::{Hash, Hasher}, mem::size_of, cmp::Reverse};
 
 use byteorder::{ReadBytesExt};
 
-use crate::utils::{ReadableWritable, ReadStructurePos, WriteStructurePos};
+use crate::{utils::{ReadableWritable, ReadStructurePos, WriteStructurePos}, determinism::SeededHashState,
+    storage::SharedStorage, trace::{Tracer, Phase}, frequency::FrequencySketch};
 
 pub const PAGE_SIZE: usize = 4096;
 pub const BLOCK_SIZE: usize = 64;
@@ -11,77 +12,109 @@ pub const PAGE_PAYLOAD_SIZE: usize = BLOCK_SIZE * PAGE_BLOCK_COUNT;
 pub const INVALID_BLOCK_INDEX: u8 = PAGE_BLOCK_COUNT as u8;
 const INVALID_PAGE_INDEX: i32 = -1;
 const MAX_PAGE_COUNT: i32 = i32::MAX;
+/// Pages per slab of the page cache, which grows and shrinks by whole slabs of 256 KiB.
+const SLAB_PAGES: usize = 64;
 
-#[repr(u8)]
-enum BlockState {
-    Free = 0,
-    Busy = 1,
-}
+/// Bits of `Page::busy_blocks` that stand for blocks.
+const ALL_BLOCKS: u64 = (1 << PAGE_BLOCK_COUNT) - 1;
 
+/// The header takes the first block slot. With 64-byte blocks there is no room for a 64th block next to any header,
+/// so the bitmap has a spare bit.
 #[derive(Clone)]
+#[repr(C)]
 struct Page {
-    first_free_block: u8,
-    block_states: [u8; PAGE_BLOCK_COUNT as usize],
+    /// First, so a page's fullness can be checked on disk by reading one byte.
+    free_block_count: u8,
+    reserved: [u8; PAGE_HEADER_RESERVED_SIZE],
+    /// Bit `i` is set if block `i` is busy.
+    busy_blocks: u64,
     blocks: [u8; PAGE_PAYLOAD_SIZE],
 }
 
+const PAGE_HEADER_RESERVED_SIZE: usize = PAGE_SIZE - PAGE_PAYLOAD_SIZE - 1 - size_of::<u64>();
+const _: () = assert!(size_of::<Page>() == PAGE_SIZE);
+
 impl Page {
     fn new() -> Page {
         Page {
-            first_free_block: 0,
-            block_states: [BlockState::Free as u8; PAGE_BLOCK_COUNT],
+            free_block_count: PAGE_BLOCK_COUNT as u8,
+            reserved: [0; PAGE_HEADER_RESERVED_SIZE],
+            busy_blocks: 0,
             blocks: [0; PAGE_PAYLOAD_SIZE],
         }
     }
 
     fn has_free_blocks(&self) -> bool {
-        self.first_free_block != INVALID_BLOCK_INDEX
+        self.free_block_count != 0
     }
 
-    fn get_block_data(&self, index: u8, offset: usize, length: usize) -> &[u8] {
-        &self.blocks[Page::get_block_data_range(index, offset, length)]
+    fn free_block_count(&self) -> usize {
+        self.free_block_count as usize
     }
 
-    fn set_block_data(&mut self, index: u8, data: &[u8], offset: usize) -> bool {
-        let block_data = &mut self.blocks[Page::get_block_data_range(index, offset, data.len())];
-        if block_data.eq(&data) {
-            return false;
+    fn first_free_block(&self) -> u8 {
+        match !self.busy_blocks & ALL_BLOCKS {
+            0 => INVALID_BLOCK_INDEX,
+            free => free.trailing_zeros() as u8,
         }
+    }
 
-        block_data.copy_from_slice(&data);
-        self.block_states[index as usize] = BlockState::Busy as u8;
+    /// Blocks past the end of the page are never busy.
+    fn is_block_busy(&self, index: u8) -> bool {
+        index < INVALID_BLOCK_INDEX && self.busy_blocks & (1 << index) != 0
+    }
 
-        if index != self.first_free_block {
-            return true;
+    fn free_block(&mut self, index: u8) -> Result<bool> {
+        check_block_index(index)?;
+        if !self.is_block_busy(index) {
+            return Ok(false);
         }
 
-        for i in index as usize..PAGE_BLOCK_COUNT {
-            if (BlockState::Free as u8) == self.block_states[i] {
-                self.first_free_block = i as u8;
-                return true;
-            }
+        self.busy_blocks &= !(1 << index);
+        self.free_block_count += 1;
+        Ok(true)
+    }
+
+    fn set_block_data(&mut self, index: u8, data: &[u8], offset: usize) -> Result<bool> {
+        let block_data = &mut self.blocks[Page::get_block_data_range(index, offset, data.len())?];
+        let busy = self.busy_blocks & (1 << index) != 0;
+        if (*block_data).eq(data) && busy {
+            return Ok(false);
+        }
+
+        block_data.copy_from_slice(data);
+        if !busy {
+            self.busy_blocks |= 1 << index;
+            self.free_block_count -= 1;
         }
 
-        self.first_free_block = INVALID_BLOCK_INDEX;
-        true
+        Ok(true)
     }
 
-    fn get_block_data_range(index: u8, offset: usize, length: usize) -> Range<usize> {
-        if index >= PAGE_BLOCK_COUNT as u8 {
-            panic!("Invalid block index {:?}", index)
-        }
+    fn get_block_data_range(index: u8, offset: usize, length: usize) -> Result<Range<usize>> {
+        check_block_index(index)?;
 
         let length = if length > 0 { length } else { BLOCK_SIZE };
 
         if offset + length > BLOCK_SIZE {
-            panic!("Offset + Length can't be greater than block size {:?}", BLOCK_SIZE)
+            return Err(Error::new(ErrorKind::InvalidInput,
+                format!("Offset + Length can't be greater than block size {:?}", BLOCK_SIZE)));
         }
 
         let start = index as usize * BLOCK_SIZE + offset;
-        start..start + length
+        Ok(start..start + length)
     }
 }
 
+/// Block indexes come from addresses stored in the file, so a bad one means corrupted data.
+fn check_block_index(index: u8) -> Result<()> {
+    if index >= INVALID_BLOCK_INDEX {
+        return Err(Error::new(ErrorKind::InvalidData, format!("Invalid block index {:?}", index)));
+    }
+
+    Ok(())
+}
+
 impl ReadableWritable for Page {
     fn read_to_buffer(read_action: impl FnOnce(&mut [u8]) -> Result<Self>) -> Result<Self> {
         let mut buffer = [0; size_of::<Self>()];
@@ -89,11 +122,36 @@ impl ReadableWritable for Page {
     }
 }
 
-#[derive(Clone, Copy, PartialEq)]
-#[repr(align(2))]
+#[derive(Clone, Copy)]
+#[repr(C, align(2))]
 pub struct BlockAddress {
     pub page_index: i32,
     pub block_index: u8,
+    /// Spelled out so it's written as zeros instead of whatever the padding held, which keeps files of the same
+    /// operations byte-identical. Ignored by comparisons, as files written before may hold anything there.
+    padding: [u8; 3],
+}
+
+impl PartialEq for BlockAddress {
+    fn eq(&self, other: &Self) -> bool {
+        self.page_index == other.page_index && self.block_index == other.block_index
+    }
+}
+
+impl Eq for BlockAddress {}
+
+impl Hash for BlockAddress {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.page_index.hash(state);
+        self.block_index.hash(state);
+    }
+}
+
+impl Debug for BlockAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BlockAddress").field("page_index", &self.page_index).field("block_index", &self.block_index)
+            .finish()
+    }
 }
 
 impl BlockAddress {
@@ -101,11 +159,12 @@ impl BlockAddress {
         BlockAddress {
             page_index: INVALID_PAGE_INDEX,
             block_index: INVALID_BLOCK_INDEX,
+            padding: [0; 3],
         }
     }
 
     pub fn new(page_index: i32, block_index: u8) -> Self {
-        BlockAddress { page_index, block_index }
+        BlockAddress { page_index, block_index, padding: [0; 3] }
     }
 
     pub const fn size_in_buffer() -> usize {
@@ -152,21 +211,72 @@ impl ReadableWritable for PagesHeader {
     }
 }
 
+/// How new records pick the page they are written to.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Allocation {
+    /// The lowest page with enough free blocks, which keeps the file compact.
+    #[default]
+    LowestFree,
+    /// The next page with enough free blocks after the one picked last, wrapping around at the end of the file.
+    /// Spreads rewrites over all pages instead of wearing out the first ones, for flash without wear leveling.
+    Rotating,
+}
+
+/// Which cached pages `Database::shrink_cache` evicts first.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum CachePolicy {
+    /// The pages at the end of the file.
+    #[default]
+    PageOrder,
+    /// The pages read least often recently, estimated like TinyLFU does, so the pages a one-off scan such as
+    /// `verify` or a full iteration went through are evicted before the hot ones. Costs 16 KiB and a few hashes
+    /// per page access.
+    TinyLfu,
+}
+
+/// The error payload of every page access on a poisoned database, see `Database::is_poisoned` and `is_poisoned`.
+#[derive(Debug)]
+pub struct Poisoned {
+    /// The error that poisoned the database.
+    pub reason: String,
+}
+
+impl Display for Poisoned {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The database is poisoned, reopen it: {}", self.reason)
+    }
+}
+
+impl std::error::Error for Poisoned {}
+
+/// Returns `true` if `error` comes from an operation that failed because the database is poisoned.
+pub fn is_poisoned(error: &Error) -> bool {
+    error.get_ref().is_some_and(|e| e.is::<Poisoned>())
+}
+
 pub struct PageManager {
     imp: Rc<RefCell<PageManagerImpl>>,
 }
 
 impl PageManager {
-    pub fn new(file: Rc<RefCell<File>>, offset: u64) -> Result<Self> {
-        Ok(PageManager { imp: Rc::new(RefCell::new(PageManagerImpl::new(file, offset)?)) })
+    pub fn new(file: SharedStorage, offset: u64, hash_state: SeededHashState, allocation: Allocation,
+        cache_policy: CachePolicy, tracer: Tracer) -> Result<Self> {
+        let imp = PageManagerImpl::new(file, offset, hash_state, allocation, cache_policy, tracer)?;
+        Ok(PageManager { imp: Rc::new(RefCell::new(imp)) })
     }
 
     pub fn get_page(&mut self, index: i32) -> Result<PageAccessor> {
         let mut imp_mut = self.imp.as_ref().borrow_mut();
+        imp_mut.check_poisoned()?;
+        let handle = imp_mut.get_page(index)?;
+        let slab = imp_mut.arena.slab(handle).clone();
+        let slot = &slab[handle % SLAB_PAGES];
+        slot.pins.set(slot.pins.get() + 1);
         Ok(PageAccessor {
             page_manager: self.imp.clone(),
-            page: imp_mut.get_page(index)?,
-            index: index,
+            slab,
+            handle,
+            index,
             has_changes: false
         })
     }
@@ -175,55 +285,223 @@ impl PageManager {
         let index = self.imp.borrow_mut().find_page_with_free_blocks(start_index)?;
         self.get_page(index)
     }
+
+    /// Finds a page with at least `count` free blocks, which is a new page past the end of the file if there is
+    /// no such page. With `Allocation::LowestFree` that is the first such page from `start_index` on; with
+    /// `Allocation::Rotating` the search starts after the page found last. Loads every page it checks into the cache.
+    pub fn find_page_with_free_block_count(&mut self, start_index: i32, count: usize) -> Result<i32> {
+        let page_count = self.page_count()?;
+        let (allocation, next_fit_start, first_page_with_free_blocks) = {
+            let imp = self.imp.borrow();
+            (imp.allocation, imp.next_fit_start, imp.header.first_page_with_free_blocks)
+        };
+        let start_index = start_index.max(first_page_with_free_blocks);
+        let found = match allocation {
+            Allocation::LowestFree => self.find_in_range(start_index..page_count, count)?,
+            Allocation::Rotating => match self.find_in_range(next_fit_start.max(start_index)..page_count, count)? {
+                Some(index) => Some(index),
+                None => self.find_in_range(start_index..next_fit_start.min(page_count), count)?,
+            },
+        };
+
+        let index = found.unwrap_or(page_count.max(start_index));
+        self.imp.borrow_mut().next_fit_start = index + 1;
+        Ok(index)
+    }
+
+    fn find_in_range(&mut self, range: Range<i32>, count: usize) -> Result<Option<i32>> {
+        for index in range {
+            if self.get_page(index)?.free_block_count() >= count {
+                return Ok(Some(index));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Number of pages stored in the file or already created in the cache.
+    pub fn page_count(&self) -> Result<i32> {
+        self.imp.borrow().page_count()
+    }
+
+    /// Extends the file with empty pages to at least `page_count` pages and makes room in the cache for as many.
+    pub fn reserve_pages(&mut self, page_count: i32) -> Result<()> {
+        self.imp.borrow_mut().reserve_pages(page_count)
+    }
+
+    pub fn cached_page_count(&self) -> usize {
+        self.imp.borrow().cached_pages.len()
+    }
+
+    /// Evicts cached pages, highest index first, until at most `max_pages` remain.
+    /// Pages in use by a `PageAccessor` stay cached. Returns the number of evicted pages.
+    pub fn evict_pages(&mut self, max_pages: usize) -> usize {
+        let mut imp = self.imp.borrow_mut();
+        let evicted = imp.evict_pages(max_pages);
+        imp.arena.release_empty_slabs();
+        evicted
+    }
+
+    pub fn cached_page_indexes(&self) -> Vec<i32> {
+        self.imp.borrow().cached_pages.keys().copied().collect()
+    }
+
+    /// Evicts the cached pages `evict` returns `true` for, except those in use by a `PageAccessor`.
+    /// Returns the number of evicted pages.
+    pub fn evict_pages_where(&mut self, mut evict: impl FnMut(i32) -> bool) -> usize {
+        let mut imp = self.imp.borrow_mut();
+        let indexes: Vec<i32> = imp.cached_pages.keys().copied().filter(|&index| evict(index)).collect();
+        let evicted = indexes.into_iter().filter(|&index| imp.evict_page(index)).count();
+        imp.arena.release_empty_slabs();
+        evicted
+    }
+
+    /// Drops all pages, truncating the file to the pages header.
+    pub fn clear(&mut self) -> Result<()> {
+        self.imp.borrow_mut().clear()
+    }
+
+    /// A page write failed while a `PageAccessor` was dropped, or `poison` was called. The cached pages may no
+    /// longer match the file, so every later page access fails with a `Poisoned` error.
+    pub fn is_poisoned(&self) -> bool {
+        self.imp.borrow().poisoned.is_some()
+    }
+
+    /// Fails with a `Poisoned` error if the page manager is poisoned, for writes that bypass the pages.
+    pub fn check_poisoned(&self) -> Result<()> {
+        self.imp.borrow().check_poisoned()
+    }
+
+    /// Fails every later page access until `reload`, after an error that leaves the file in an unknown state.
+    pub fn poison(&mut self, reason: String) {
+        self.imp.borrow_mut().poisoned.get_or_insert(reason);
+    }
+
+    /// Drops the cached pages and lifts the poison, so pages are read from the file again.
+    /// Must not be called while a `PageAccessor` is alive.
+    pub fn reload(&mut self) -> Result<()> {
+        self.imp.borrow_mut().reload()
+    }
+
+    /// Starts recording the blocks that become busy, for `roll_back` after a failed write.
+    pub fn start_block_log(&mut self) {
+        self.imp.borrow_mut().taken_blocks = Some(Vec::new());
+    }
+
+    /// Stops recording and returns the blocks that became busy since `start_block_log`.
+    pub fn finish_block_log(&mut self) -> Vec<BlockAddress> {
+        self.imp.borrow_mut().taken_blocks.take().unwrap_or_default()
+    }
+
+    /// Returns to what the file holds after a write failed part way, e.g. on a full disk: drops the cached pages,
+    /// whose changes may not have reached the file, which also lifts the poison, and frees `taken_blocks` again.
+    /// Must not be called while a `PageAccessor` is alive.
+    pub fn roll_back(&mut self, taken_blocks: &[BlockAddress]) -> Result<()> {
+        self.imp.borrow_mut().reload()?;
+        for address in taken_blocks {
+            let mut page = self.get_page(address.page_index)?;
+            page.free_block(address.block_index)?;
+            page.commit()?;
+        }
+
+        Ok(())
+    }
 }
 
 struct PageManagerImpl {
-    file: Rc<RefCell<File>>,
+    file: SharedStorage,
     header_offset: u64,
     first_page_offset: u64,
     header: PagesHeader,
-    cached_pages: HashMap<i32, Rc<RefCell<Page>>>,
+    /// Handles of the cached pages in `arena`.
+    cached_pages: HashMap<i32, usize, SeededHashState>,
+    arena: PageArena,
+    poisoned: Option<String>,
+    taken_blocks: Option<Vec<BlockAddress>>,
+    allocation: Allocation,
+    /// Where `Allocation::Rotating` continues its search, just past the page it picked last.
+    next_fit_start: i32,
+    /// Page accesses counted for `CachePolicy::TinyLfu`.
+    frequencies: Option<FrequencySketch>,
+    tracer: Tracer,
 }
 
 impl PageManagerImpl {
-    fn new(file: Rc<RefCell<File>>, offset: u64) -> Result<Self> {
-        let pages_header = if file.borrow().metadata()?.len() <= offset {
-            PagesHeader::default()
+    fn new(file: SharedStorage, offset: u64, hash_state: SeededHashState, allocation: Allocation,
+        cache_policy: CachePolicy, tracer: Tracer) -> Result<Self> {
+        let first_page_offset = offset + PagesHeader::size_in_buffer() as u64;
+        let frequencies = (cache_policy == CachePolicy::TinyLfu).then(FrequencySketch::new);
+        let mut page_manager = PageManagerImpl { file, header_offset: offset, first_page_offset,
+            header: PagesHeader::default(), cached_pages: HashMap::with_hasher(hash_state), arena: PageArena::default(), poisoned: None,
+            taken_blocks: None, allocation, next_fit_start: 0, frequencies, tracer };
+        page_manager.header = page_manager.read_header()?;
+        Ok(page_manager)
+    }
+
+    fn read_header(&self) -> Result<PagesHeader> {
+        if self.file.borrow().size()? <= self.header_offset {
+            return Ok(PagesHeader::default());
         }
-        else {
-            file.borrow_mut().read_structure_from_pos(offset)?
-        };
 
-        let first_page_offset = offset + PagesHeader::size_in_buffer() as u64;
+        self.file.borrow_mut().read_structure_from_pos(self.header_offset)
+    }
+
+    fn reload(&mut self) -> Result<()> {
+        self.drop_cached_pages();
+        self.poisoned = None;
+
+        // Pages are written whole, so a partial page at the end is a write that failed half way.
+        let file_len = self.file.borrow().size()?;
+        if file_len > self.first_page_offset {
+            let stored_pages = (file_len - self.first_page_offset) / PAGE_SIZE as u64;
+            let pages_end = self.get_page_address(stored_pages as i32);
+            if pages_end < file_len {
+                self.file.borrow_mut().set_size(pages_end)?;
+            }
+        }
 
-        Ok(PageManagerImpl { file, header_offset: offset, first_page_offset, header: pages_header, cached_pages: HashMap::new() })
+        self.header = self.read_header()?;
+        Ok(())
     }
 
-    fn get_page(&mut self, index: i32) -> Result<Rc<RefCell<Page>>> {
-        if index < 0 || index >= MAX_PAGE_COUNT {
-            panic!("Invalid page index {:?}", index);
+    /// Returns the handle of page `index` in `arena`, loading the page if it isn't cached.
+    fn get_page(&mut self, index: i32) -> Result<usize> {
+        if !(0..MAX_PAGE_COUNT).contains(&index) {
+            return Err(Error::new(ErrorKind::InvalidData, format!("Invalid page index {:?}", index)));
+        }
+
+        if let Some(frequencies) = &mut self.frequencies {
+            frequencies.record(index);
         }
 
-        if let Some(p) = self.cached_pages.get(&index) {
-            Ok(p.clone())
+        if let Some(&handle) = self.cached_pages.get(&index) {
+            Ok(handle)
         }
         else {
+            let _span = self.tracer.span(Phase::PageLoad);
             let page_address = self.get_page_address(index);
-            let new_page = if self.file.borrow().metadata()?.len() <= page_address {
+            let new_page = if self.file.borrow().size()? <= page_address {
                 Page::new()
             }
             else {
                 self.file.borrow_mut().read_structure_from_pos(page_address)?
             };
 
-            let page = Rc::new(RefCell::new(new_page));
-            let cloned_page = page.clone();
-            self.cached_pages.insert(index, page);
-            Ok(cloned_page)
+            let handle = self.arena.allocate(new_page);
+            self.cached_pages.insert(index, handle);
+            Ok(handle)
+        }
+    }
+
+    fn check_poisoned(&self) -> Result<()> {
+        match &self.poisoned {
+            Some(reason) => Err(Error::other(Poisoned { reason: reason.clone() })),
+            None => Ok(()),
         }
     }
 
     fn commit_page(&mut self, index: i32, page: &Page) -> Result<()> {
+        let _span = self.tracer.span(Phase::PageWrite);
         self.file.borrow_mut().write_structure_to_pos(self.get_page_address(index), page)?;
 
         if index == self.header.first_page_with_free_blocks && !page.has_free_blocks() {
@@ -237,28 +515,114 @@ impl PageManagerImpl {
         Ok(())
     }
 
+    fn evict_pages(&mut self, max_pages: usize) -> usize {
+        let mut indexes: Vec<i32> = self.cached_pages.keys().copied().collect();
+        let frequencies = self.frequencies.as_ref();
+        indexes.sort_unstable_by_key(|&index| (frequencies.map_or(0, |f| f.frequency(index)), Reverse(index)));
+
+        let mut evicted = 0;
+        for index in indexes {
+            if self.cached_pages.len() <= max_pages {
+                break;
+            }
+
+            if self.evict_page(index) {
+                evicted += 1;
+            }
+        }
+
+        evicted
+    }
+
+    /// Evicts page `index` unless it's in use by a `PageAccessor`.
+    fn evict_page(&mut self, index: i32) -> bool {
+        // Changes are written through when an accessor is dropped, so an unused page is never dirty.
+        match self.cached_pages.get(&index) {
+            Some(&handle) if self.arena.slot(handle).pins.get() == 0 => {
+                self.cached_pages.remove(&index);
+                self.arena.free(handle);
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Empties the cache. Pages still in use by a `PageAccessor` are freed when it's dropped.
+    fn drop_cached_pages(&mut self) {
+        for (_, handle) in self.cached_pages.drain() {
+            let slot = self.arena.slot(handle);
+            if slot.pins.get() == 0 {
+                self.arena.free(handle);
+            }
+            else {
+                slot.detached.set(true);
+            }
+        }
+
+        self.arena.release_empty_slabs();
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.drop_cached_pages();
+        self.file.borrow_mut().set_size(self.first_page_offset)?;
+        self.update_first_page_with_free_blocks(0)
+    }
+
+    fn page_count(&self) -> Result<i32> {
+        let file_len = self.file.borrow().size()?;
+        let stored_pages = file_len.saturating_sub(self.first_page_offset).div_ceil(PAGE_SIZE as u64) as i32;
+        let cached_pages = self.cached_pages.keys().max().map_or(0, |index| index + 1);
+        Ok(stored_pages.max(cached_pages))
+    }
+
+    fn reserve_pages(&mut self, page_count: i32) -> Result<()> {
+        let additional = (page_count as usize).saturating_sub(self.cached_pages.len());
+        self.cached_pages.reserve(additional);
+
+        let file_len = self.file.borrow().size()?;
+        let stored_pages = file_len.saturating_sub(self.first_page_offset).div_ceil(PAGE_SIZE as u64) as i32;
+        if page_count <= stored_pages {
+            return Ok(());
+        }
+
+        // Grown in one step; a zeroed page counts as full, so the reserved pages are initialized right after.
+        self.file.borrow_mut().set_size(self.get_page_address(page_count))?;
+        self.write_header()?;
+        let empty_page = Page::new();
+        for index in stored_pages..page_count {
+            self.file.borrow_mut().write_structure_to_pos(self.get_page_address(index), &empty_page)?;
+        }
+
+        Ok(())
+    }
+
     fn get_page_address(&self, index: i32) -> u64 {
         self.first_page_offset + (index as usize * PAGE_SIZE) as u64
     }
 
     fn update_first_page_with_free_blocks(&mut self, index: i32) -> Result<()> {
         self.header.first_page_with_free_blocks = index;
+        self.write_header()
+    }
+
+    fn write_header(&mut self) -> Result<()> {
         self.file.borrow_mut().write_structure_to_pos(self.header_offset, &self.header)
     }
 
     fn find_page_with_free_blocks(&mut self, start: i32) -> Result<i32> {
         for index in start..MAX_PAGE_COUNT {
-            if let Some(page) = self.cached_pages.get(&index) {
-                if page.as_ref().borrow().has_free_blocks() { return Ok(index); }
+            if let Some(&handle) = self.cached_pages.get(&index) {
+                if self.arena.slot(handle).page.borrow().has_free_blocks() { return Ok(index); }
+                continue;
             }
 
             let page_address = self.get_page_address(index);
-            if self.file.borrow().metadata()?.len() <= page_address {
+            if self.file.borrow().size()? <= page_address {
                 return Ok(index);
             }
 
             self.file.borrow_mut().seek(std::io::SeekFrom::Start(page_address))?;
-            if self.file.borrow_mut().read_u8()? != INVALID_BLOCK_INDEX {
+            if self.file.borrow_mut().read_u8()? != 0 {
                 return Ok(index);
             }
         }
@@ -267,37 +631,145 @@ impl PageManagerImpl {
     }
 }
 
+/// A cached page, at `handle % SLAB_PAGES` of a slab.
+struct Slot {
+    page: RefCell<Page>,
+    /// `PageAccessor`s using the page, which keep it from being evicted.
+    pins: Cell<u32>,
+    /// Dropped from the cache while pinned, so the last accessor frees the slot.
+    detached: Cell<bool>,
+}
+
+/// Storage of the cached pages in slabs, so the cache doesn't allocate per page. A page is addressed by its handle:
+/// the number of its slab times `SLAB_PAGES` plus its slot in the slab. Slabs are shared with the accessors of
+/// their pages, so page data stays borrowable while the arena changes.
+#[derive(Default)]
+struct PageArena {
+    /// `None` for slabs given back by `release_empty_slabs`, so the handles of later slabs stay valid.
+    slabs: Vec<Option<Rc<[Slot]>>>,
+    free_slots: Vec<usize>,
+}
+
+impl PageArena {
+    fn slab(&self, handle: usize) -> &Rc<[Slot]> {
+        self.slabs[handle / SLAB_PAGES].as_ref().expect("Handles only point into live slabs")
+    }
+
+    fn slot(&self, handle: usize) -> &Slot {
+        &self.slab(handle)[handle % SLAB_PAGES]
+    }
+
+    fn allocate(&mut self, page: Page) -> usize {
+        let handle = match self.free_slots.pop() {
+            Some(handle) => handle,
+            None => {
+                let slab_index = self.slabs.iter().position(Option::is_none).unwrap_or(self.slabs.len());
+                if slab_index == self.slabs.len() {
+                    self.slabs.push(None);
+                }
+
+                let slots = (0..SLAB_PAGES)
+                    .map(|_| Slot { page: RefCell::new(Page::new()), pins: Cell::new(0), detached: Cell::new(false) });
+                self.slabs[slab_index] = Some(slots.collect());
+                // Popped lowest first.
+                self.free_slots.extend((1..SLAB_PAGES).rev().map(|slot| slab_index * SLAB_PAGES + slot));
+                slab_index * SLAB_PAGES
+            },
+        };
+
+        *self.slot(handle).page.borrow_mut() = page;
+        handle
+    }
+
+    fn free(&mut self, handle: usize) {
+        self.free_slots.push(handle);
+    }
+
+    /// Gives back the memory of slabs without cached pages.
+    fn release_empty_slabs(&mut self) {
+        let mut free_counts = vec![0; self.slabs.len()];
+        self.free_slots.iter().for_each(|handle| free_counts[handle / SLAB_PAGES] += 1);
+        for (slab, free_count) in self.slabs.iter_mut().zip(free_counts) {
+            if free_count == SLAB_PAGES {
+                *slab = None;
+            }
+        }
+
+        let slabs = &self.slabs;
+        self.free_slots.retain(|handle| slabs[handle / SLAB_PAGES].is_some());
+    }
+}
+
 pub struct PageAccessor {
     page_manager: Rc<RefCell<PageManagerImpl>>,
-    page: Rc<RefCell<Page>>,
+    slab: Rc<[Slot]>,
+    handle: usize,
     index: i32,
     has_changes: bool,
 }
 
 impl PageAccessor {
-    pub fn get_block_data(&self, index: u8, offset: usize, length: usize) -> Ref<[u8]> {
-        Ref::map(self.page.as_ref().borrow(), |p| p.get_block_data(index, offset, length))
+    fn slot(&self) -> &Slot {
+        &self.slab[self.handle % SLAB_PAGES]
     }
 
-    pub fn set_block_data(&mut self, index: u8, data: &[u8], offset: usize) {
-        self.has_changes = self.page.as_ref().borrow_mut().set_block_data(index, data, offset) || self.has_changes;
+    /// Data of a block. Fails with `ErrorKind::ResourceBusy` while another accessor of the page is changing it.
+    pub fn get_block_data(&self, index: u8, offset: usize, length: usize) -> Result<Ref<'_, [u8]>> {
+        let range = Page::get_block_data_range(index, offset, length)?;
+        let page = self.slot().page.try_borrow().map_err(|_| Error::new(ErrorKind::ResourceBusy,
+            format!("Page {:?} can't be read while it's being changed", self.index)))?;
+        Ok(Ref::map(page, |p| &p.blocks[range]))
+    }
+
+    pub fn set_block_data(&mut self, index: u8, data: &[u8], offset: usize) -> Result<()> {
+        let was_busy = self.is_block_busy(index);
+        self.has_changes = self.page_mut()?.set_block_data(index, data, offset)? || self.has_changes;
+        if !was_busy {
+            if let Some(taken_blocks) = &mut self.page_manager.borrow_mut().taken_blocks {
+                taken_blocks.push(BlockAddress::new(self.index, index));
+            }
+        }
+
+        Ok(())
     }
 
     pub fn has_free_blocks(&self) -> bool {
-        self.page.borrow().has_free_blocks()
+        self.slot().page.borrow().has_free_blocks()
+    }
+
+    pub fn free_block_count(&self) -> usize {
+        self.slot().page.borrow().free_block_count()
     }
 
     pub fn first_free_block(&self) -> u8 {
-        self.page.borrow().first_free_block
+        self.slot().page.borrow().first_free_block()
+    }
+
+    pub fn is_block_busy(&self, index: u8) -> bool {
+        self.slot().page.borrow().is_block_busy(index)
+    }
+
+    pub fn free_block(&mut self, index: u8) -> Result<()> {
+        self.has_changes = self.page_mut()?.free_block(index)? || self.has_changes;
+        Ok(())
     }
 
     pub fn index(&self) -> i32 {
         self.index
     }
 
+    /// The page for a change. Accessors of the same page share it, so this fails with `ErrorKind::ResourceBusy`
+    /// instead of panicking while data returned by `get_block_data` of any of them is still borrowed.
+    fn page_mut(&self) -> Result<RefMut<'_, Page>> {
+        self.slot().page.try_borrow_mut().map_err(|_| Error::new(ErrorKind::ResourceBusy,
+            format!("Page {:?} can't be changed while its data is borrowed", self.index)))
+    }
+
+    /// Writes the page to the file if it has changed since the last commit.
     pub fn commit(&mut self) -> Result<()> {
         if self.has_changes {
-            return self.page_manager.borrow_mut().commit_page(self.index, &mut *self.page.borrow_mut())
+            self.page_manager.borrow_mut().commit_page(self.index, &self.slot().page.borrow())?;
+            self.has_changes = false;
         }
 
         Ok(())
@@ -305,7 +777,65 @@ impl PageAccessor {
 }
 
 impl Drop for PageAccessor {
+    /// Commits what the owner didn't commit explicitly. A failure can't be returned from here, so it poisons
+    /// the page manager instead.
     fn drop(&mut self) {
-        self.commit().unwrap();
+        if let Err(error) = self.commit() {
+            self.page_manager.borrow_mut().poisoned.get_or_insert(error.to_string());
+        }
+
+        let slot = self.slot();
+        slot.pins.set(slot.pins.get() - 1);
+        if slot.pins.get() == 0 && slot.detached.get() {
+            slot.detached.set(false);
+            self.page_manager.borrow_mut().arena.free(self.handle);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, io::{Cursor, ErrorKind}, rc::Rc};
+
+    use crate::{determinism::SeededHashState, storage::{SharedStorage, Storage}, trace::Tracer};
+
+    use super::{Allocation, CachePolicy, PageManager};
+
+    fn page_manager() -> PageManager {
+        let storage: Box<dyn Storage> = Box::new(Cursor::new(Vec::new()));
+        let file: SharedStorage = Rc::new(RefCell::new(storage));
+        PageManager::new(file, 0, SeededHashState::new(0), Allocation::LowestFree, CachePolicy::default(),
+            Tracer::default()).unwrap()
+    }
+
+    #[test]
+    fn write_while_other_accessor_reads_is_busy() {
+        let mut page_manager = page_manager();
+        let mut writer = page_manager.get_page_with_free_blocks(0).unwrap();
+        writer.set_block_data(0, b"before", 0).unwrap();
+        let reader = page_manager.get_page(writer.index()).unwrap();
+
+        let data = reader.get_block_data(0, 0, 6).unwrap();
+        assert_eq!(writer.set_block_data(0, b"after", 0).unwrap_err().kind(), ErrorKind::ResourceBusy);
+        assert_eq!(writer.free_block(0).unwrap_err().kind(), ErrorKind::ResourceBusy);
+        assert_eq!(&*writer.get_block_data(0, 0, 6).unwrap(), b"before");
+        assert_eq!(&*data, b"before");
+        drop(data);
+
+        writer.set_block_data(0, b"after", 0).unwrap();
+        assert_eq!(&*reader.get_block_data(0, 0, 5).unwrap(), b"after");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn read_while_other_accessor_writes_is_busy() {
+        let mut page_manager = page_manager();
+        let writer = page_manager.get_page_with_free_blocks(0).unwrap();
+        let reader = page_manager.get_page(writer.index()).unwrap();
+
+        let page = writer.page_mut().unwrap();
+        assert_eq!(reader.get_block_data(0, 0, 1).unwrap_err().kind(), ErrorKind::ResourceBusy);
+        drop(page);
+
+        reader.get_block_data(0, 0, 1).unwrap();
+    }
+}
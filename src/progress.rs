@@ -0,0 +1,42 @@
+use std::{io::{Result, Error}, ops::ControlFlow, fmt::{self, Display}};
+
+/// How far a long operation such as `defragment_all`, `verify`, an import or an upgrade has got.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Progress {
+    /// Records processed so far.
+    pub done: u64,
+    /// Units of work in total, if known without an extra pass over the file.
+    pub total: Option<u64>,
+}
+
+/// The error payload of an operation cancelled by its progress callback, see `is_cancelled`.
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("The operation was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Returns `true` if `error` comes from an operation cancelled by its progress callback.
+pub fn is_cancelled(error: &Error) -> bool {
+    error.get_ref().is_some_and(|e| e.is::<Cancelled>())
+}
+
+/// Reports progress to `callback`, turning a request to stop into a `Cancelled` error.
+/// Only called where stopping leaves the database consistent.
+pub(crate) fn report(callback: &mut impl FnMut(Progress) -> ControlFlow<()>, done: u64, total: Option<u64>)
+    -> Result<()> {
+    match callback(Progress { done, total }) {
+        ControlFlow::Continue(()) => Ok(()),
+        ControlFlow::Break(()) => Err(Error::other(Cancelled)),
+    }
+}
+
+/// The callback of the variants without progress reporting.
+pub(crate) fn ignore(_: Progress) -> ControlFlow<()> {
+    ControlFlow::Continue(())
+}
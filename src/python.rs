@@ -0,0 +1,66 @@
+use std::io::{Error, ErrorKind};
+
+use pyo3::{prelude::*, exceptions::{PyKeyError, PyOSError, PyValueError}, types::PyBytes};
+
+/// `Database` as a Python mapping from `str` keys to `bytes` values.
+#[pyclass(name = "Database", unsendable)]
+struct PyDatabase {
+    db: crate::Database,
+}
+
+#[pymethods]
+impl PyDatabase {
+    /// Opens or creates the database at `path`.
+    #[staticmethod]
+    fn open(path: &str) -> PyResult<Self> {
+        Ok(PyDatabase { db: crate::Database::new(path).map_err(to_py_error)? })
+    }
+
+    fn __getitem__<'py>(&mut self, py: Python<'py>, key: &str) -> PyResult<Bound<'py, PyBytes>> {
+        let mut value = Vec::new();
+        if !self.db.get_into(key, &mut value).map_err(to_py_error)? {
+            return Err(PyKeyError::new_err(key.to_owned()));
+        }
+
+        Ok(PyBytes::new(py, &value))
+    }
+
+    /// Replaces the value of `key` like a `dict` does; the record moves to the end of the insertion order.
+    fn __setitem__(&mut self, key: &str, value: &[u8]) -> PyResult<()> {
+        self.db.put(key, value).map_err(to_py_error)
+    }
+
+    fn __delitem__(&mut self, key: &str) -> PyResult<()> {
+        match self.db.delete(key).map_err(to_py_error)? {
+            true => Ok(()),
+            false => Err(PyKeyError::new_err(key.to_owned())),
+        }
+    }
+
+    fn __contains__(&mut self, key: &str) -> PyResult<bool> {
+        Ok(self.db.metadata(key).map_err(to_py_error)?.is_some())
+    }
+
+    /// All records as a list of `(key, value)` tuples, in insertion order.
+    fn items<'py>(&mut self, py: Python<'py>) -> PyResult<Vec<(String, Bound<'py, PyBytes>)>> {
+        self.db.iter()
+            .map(|record| record.map(|(key, value)| (key, PyBytes::new(py, &value))).map_err(to_py_error))
+            .collect()
+    }
+
+    fn sync(&mut self) -> PyResult<()> {
+        self.db.sync().map_err(to_py_error)
+    }
+}
+
+fn to_py_error(error: Error) -> PyErr {
+    match error.kind() {
+        ErrorKind::InvalidInput | ErrorKind::InvalidData => PyValueError::new_err(error.to_string()),
+        _ => PyOSError::new_err(error.to_string()),
+    }
+}
+
+#[pymodule]
+fn key_value_db(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyDatabase>()
+}
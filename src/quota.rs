@@ -0,0 +1,67 @@
+use std::io::{Result, Error, ErrorKind};
+
+/// A quota set with `Database::set_quota` and the bytes its prefix takes.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct QuotaUsage {
+    /// Bytes of the blocks of the records' own chains under the prefix, like `SizeEstimate::bytes`.
+    pub used_bytes: u64,
+    pub max_bytes: u64,
+}
+
+/// The quotas of an open database, with their usage kept up to date as records are written and removed.
+#[derive(Default)]
+pub(crate) struct Quotas {
+    rules: Vec<(Vec<u8>, QuotaUsage)>,
+}
+
+impl Quotas {
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn prefixes(&self) -> Vec<String> {
+        self.rules.iter().map(|(prefix, _)| String::from_utf8_lossy(prefix).into_owned()).collect()
+    }
+
+    pub fn usage(&self, prefix: &str) -> Option<QuotaUsage> {
+        self.rules.iter().find(|(p, _)| p == prefix.as_bytes()).map(|&(_, usage)| usage)
+    }
+
+    pub fn set(&mut self, prefix: &str, usage: QuotaUsage) {
+        self.remove(prefix);
+        self.rules.push((prefix.as_bytes().to_vec(), usage));
+    }
+
+    pub fn remove(&mut self, prefix: &str) {
+        self.rules.retain(|(p, _)| p != prefix.as_bytes());
+    }
+
+    /// Fails with `ErrorKind::QuotaExceeded` if `bytes` more under `key` would exceed one of the quotas.
+    pub fn check(&self, key: &[u8], bytes: u64) -> Result<()> {
+        let exceeded = self.rules.iter()
+            .find(|(prefix, usage)| key.starts_with(prefix) && usage.used_bytes + bytes > usage.max_bytes);
+        match exceeded {
+            Some((prefix, usage)) => Err(Error::new(ErrorKind::QuotaExceeded, format!(
+                "The quota of {:?} is exceeded: {:?} of {:?} bytes used, {:?} more needed",
+                String::from_utf8_lossy(prefix), usage.used_bytes, usage.max_bytes, bytes))),
+            None => Ok(()),
+        }
+    }
+
+    /// Counts all usage as zero, after all records were removed.
+    pub fn reset_usage(&mut self) {
+        self.rules.iter_mut().for_each(|(_, usage)| usage.used_bytes = 0);
+    }
+
+    pub fn charge(&mut self, key: &[u8], bytes: u64) {
+        self.matching(key).for_each(|usage| usage.used_bytes += bytes);
+    }
+
+    pub fn release(&mut self, key: &[u8], bytes: u64) {
+        self.matching(key).for_each(|usage| usage.used_bytes = usage.used_bytes.saturating_sub(bytes));
+    }
+
+    fn matching<'a>(&'a mut self, key: &'a [u8]) -> impl Iterator<Item = &'a mut QuotaUsage> {
+        self.rules.iter_mut().filter(move |(prefix, _)| key.starts_with(prefix)).map(|(_, usage)| usage)
+    }
+}
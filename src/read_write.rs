@@ -1,8 +1,8 @@
-use std::io::{Write, Read, Result, Error};
+use std::{collections::VecDeque, io::{Write, Read, Result, Error, ErrorKind}};
 
-use crate::{paging::{PageManager, BlockAddress, PageAccessor, BLOCK_SIZE}, utils::{ArrayStructReaderWriter}};
+use crate::{paging::{PageManager, BlockAddress, PageAccessor, BLOCK_SIZE, PAGE_BLOCK_COUNT}, utils::{ArrayStructReaderWriter}};
 
-const BLOCK_DATA_SIZE: usize = BLOCK_SIZE - BlockAddress::size_in_buffer();
+pub const BLOCK_DATA_SIZE: usize = BLOCK_SIZE - BlockAddress::size_in_buffer();
 
 pub struct PageReader<'a> {
     page_manager: &'a mut PageManager,
@@ -17,14 +17,14 @@ impl<'a> Read for PageReader<'a> {
         let mut data = buf;
         let mut read_bytes: usize = 0;
 
-        while data.len() > 0 {
+        while !data.is_empty() {
             let remaining_block_space = BLOCK_DATA_SIZE - self.block_offset;
             if data.len() <= remaining_block_space {
-                self.copy_block(&mut data);
+                self.copy_block(data)?;
                 return Ok(buf_len);
             }
 
-            self.copy_block(&mut data[..remaining_block_space]);
+            self.copy_block(&mut data[..remaining_block_space])?;
             read_bytes += remaining_block_space;
             data = &mut data[remaining_block_space..];
 
@@ -48,6 +48,10 @@ impl<'a> PageReader<'a> {
         })
     }
 
+    pub fn page_manager(&mut self) -> &mut PageManager {
+        self.page_manager
+    }
+
     pub fn skip(&mut self, skip: usize) -> Result<()> {
         let mut skip_mut = skip;
         loop {
@@ -58,20 +62,41 @@ impl<'a> PageReader<'a> {
             }
 
             if !self.go_to_next_block()? {
-                return Err(Error::new(std::io::ErrorKind::Other, "Skip too big"));
+                return Err(Error::other("Skip too big"));
             }
 
             skip_mut -= remaining_block_space;
         }
     }
 
+    /// Overwrites bytes at the current position, following the existing chain instead of allocating blocks.
+    /// Every changed page is committed before this returns.
+    pub fn overwrite(&mut self, buf: &[u8]) -> Result<()> {
+        let mut data = buf;
+        loop {
+            let remaining_block_space = BLOCK_DATA_SIZE - self.block_offset;
+            if data.len() <= remaining_block_space {
+                self.overwrite_block(data)?;
+                return self.current_page.commit();
+            }
+
+            self.overwrite_block(&data[..remaining_block_space])?;
+            data = &data[remaining_block_space..];
+
+            if !self.go_to_next_block()? {
+                return Err(Error::new(ErrorKind::UnexpectedEof, "Chain is shorter than the data"));
+            }
+        }
+    }
+
     fn go_to_next_block(&mut self) -> Result<bool> {
-        let next_block_address = get_next_block_address(&self.current_page, self.block_index);
+        let next_block_address = get_next_block_address(&self.current_page, self.block_index)?;
         if next_block_address == BlockAddress::invalid() {
             return Ok(false);
         }
 
         if next_block_address.page_index != self.current_page.index() {
+            self.current_page.commit()?;
             self.current_page = self.page_manager.get_page(next_block_address.page_index)?;
         }
 
@@ -81,11 +106,26 @@ impl<'a> PageReader<'a> {
         Ok(true)
     }
 
-    fn copy_block(&mut self, buffer: &mut [u8]) {
+    fn copy_block(&mut self, buffer: &mut [u8]) -> Result<()> {
+        if buffer.is_empty() {
+            return Ok(());
+        }
+
         let data_ref = self.current_page.get_block_data(self.block_index, self.block_offset,
-             buffer.len());
+             buffer.len())?;
         buffer.copy_from_slice(data_ref.as_ref());
         self.block_offset += buffer.len();
+        Ok(())
+    }
+
+    fn overwrite_block(&mut self, data: &[u8]) -> Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        self.current_page.set_block_data(self.block_index, data, self.block_offset)?;
+        self.block_offset += data.len();
+        Ok(())
     }
 }
 
@@ -95,6 +135,8 @@ pub struct PageWriter<'a> {
     block_address: BlockAddress,
     block_offset: usize,
     start_address: BlockAddress,
+    /// Blocks picked up front by `with_capacity`, used before looking for free blocks one by one.
+    reserved_blocks: VecDeque<BlockAddress>,
 }
 
 impl<'a> Write for PageWriter<'a> {
@@ -103,11 +145,11 @@ impl<'a> Write for PageWriter<'a> {
         loop {
             let remaining_block_space = BLOCK_DATA_SIZE - self.block_offset;
             if data.len() <= remaining_block_space {
-                self.copy_to_block(data);
+                self.copy_to_block(data)?;
                 return Ok(buf.len());
             }
 
-            self.copy_to_block(&buf[..remaining_block_space]);
+            self.copy_to_block(&data[..remaining_block_space])?;
             self.go_to_next_block()?;
 
             data = &data[remaining_block_space..];
@@ -115,14 +157,14 @@ impl<'a> Write for PageWriter<'a> {
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
-        self.flush_final_block();
-        Ok(())
+        self.flush_final_block()
     }
 }
 
 impl<'a> PageWriter<'a> {
-    pub fn new(page_manager: &'a mut PageManager) -> Result<Self> {
-        let page = page_manager.get_page_with_free_blocks(0)?;
+    /// Starts writing at the first free block of page `page_index`, or of the next page with free blocks.
+    pub fn starting_at(page_manager: &'a mut PageManager, page_index: i32) -> Result<Self> {
+        let page = page_manager.get_page_with_free_blocks(page_index)?;
         let start_address = BlockAddress::new(page.index(), page.first_free_block());
         Ok(PageWriter {
             page_manager,
@@ -130,38 +172,90 @@ impl<'a> PageWriter<'a> {
             block_address: start_address,
             start_address,
             block_offset: 0,
+            reserved_blocks: VecDeque::new(),
         })
     }
 
-    pub fn start_address(&self) -> BlockAddress {
-        self.start_address
+    /// Picks all blocks for `capacity` bytes in one decision, so a record's blocks stay on as few and as close
+    /// pages as possible: free blocks of the first page that can hold them all or, if they don't fit into a page,
+    /// consecutive new pages at the end of the file. Writing more than `capacity` bytes continues block by block.
+    pub fn with_capacity(page_manager: &'a mut PageManager, capacity: usize) -> Result<Self> {
+        let block_count = capacity.div_ceil(BLOCK_DATA_SIZE).max(1);
+        let reserved_blocks: VecDeque<_> = if block_count <= PAGE_BLOCK_COUNT {
+            let page_index = page_manager.find_page_with_free_block_count(0, block_count)?;
+            let page = page_manager.get_page(page_index)?;
+            (0..PAGE_BLOCK_COUNT as u8)
+                .filter(|&b| !page.is_block_busy(b))
+                .take(block_count)
+                .map(|b| BlockAddress::new(page.index(), b))
+                .collect()
+        }
+        else {
+            let first_page = page_manager.page_count()?;
+            (0..block_count)
+                .map(|i| BlockAddress::new(first_page + (i / PAGE_BLOCK_COUNT) as i32, (i % PAGE_BLOCK_COUNT) as u8))
+                .collect()
+        };
+
+        let mut writer = PageWriter::starting_at(page_manager, reserved_blocks[0].page_index)?;
+        writer.reserved_blocks = reserved_blocks;
+        writer.block_address = writer.reserved_blocks.pop_front().unwrap();
+        writer.start_address = writer.block_address;
+        Ok(writer)
+    }
+
+    /// Terminates the chain and commits the last page. Returns the address of the first block.
+    pub fn finish(mut self) -> Result<BlockAddress> {
+        self.flush_final_block()?;
+        self.current_page.commit()?;
+        Ok(self.start_address)
     }
 
-    fn copy_to_block(&mut self, buf: &[u8]) {
-        self.current_page.set_block_data(self.block_address.block_index, buf, self.block_offset);
+    fn copy_to_block(&mut self, buf: &[u8]) -> Result<()> {
+        if buf.is_empty() {
+            return Ok(());
+        }
+
+        self.current_page.set_block_data(self.block_address.block_index, buf, self.block_offset)?;
         self.block_offset += buf.len();
+        Ok(())
     }
 
     fn go_to_next_block(&mut self) -> Result<()> {
         self.block_offset = 0;
 
-        if !self.current_page.has_free_blocks() {
-            self.current_page = self.page_manager.get_page_with_free_blocks(self.current_page.index() + 1)?;
+        let prev_block_address = self.block_address;
+        match self.reserved_blocks.pop_front() {
+            Some(address) => {
+                if address.page_index != self.current_page.index() {
+                    self.current_page.commit()?;
+                    self.current_page = self.page_manager.get_page(address.page_index)?;
+                }
+
+                self.block_address = address;
+            },
+            None => {
+                if !self.current_page.has_free_blocks() {
+                    self.current_page.commit()?;
+                    self.current_page = self.page_manager.get_page_with_free_blocks(self.current_page.index() + 1)?;
+                }
+
+                self.block_address = BlockAddress::new(self.current_page.index(), self.current_page.first_free_block());
+            },
         }
 
         let current_page = &mut self.current_page;
 
-        let prev_block_address = self.block_address;
-        self.block_address = BlockAddress::new(current_page.index(), current_page.first_free_block());
-
-        set_next_block_address(current_page, self.block_address.block_index, BlockAddress::invalid());
+        set_next_block_address(current_page, self.block_address.block_index, BlockAddress::invalid())?;
         if prev_block_address != BlockAddress::invalid() {
-            let BlockAddress { page_index: prev_page_index, block_index: prev_block_index } = prev_block_address;
+            let BlockAddress { page_index: prev_page_index, block_index: prev_block_index, .. } = prev_block_address;
             if prev_page_index == current_page.index() {
-                set_next_block_address(current_page, prev_block_index, self.block_address);
+                set_next_block_address(current_page, prev_block_index, self.block_address)?;
             }
             else {
-                set_next_block_address(&mut self.page_manager.get_page(prev_page_index)?, prev_block_index, self.block_address);
+                let mut prev_page = self.page_manager.get_page(prev_page_index)?;
+                set_next_block_address(&mut prev_page, prev_block_index, self.block_address)?;
+                prev_page.commit()?;
             }
         }
 
@@ -172,25 +266,55 @@ impl<'a> PageWriter<'a> {
         Ok(())
     }
 
-    fn flush_final_block(&mut self) {
-        set_next_block_address(&mut self.current_page, self.block_address.block_index, BlockAddress::invalid());
+    fn flush_final_block(&mut self) -> Result<()> {
+        set_next_block_address(&mut self.current_page, self.block_address.block_index, BlockAddress::invalid())
     }
 }
 
 impl<'a> Drop for PageWriter<'a> {
     fn drop(&mut self) {
-        self.flush_final_block();
+        // The writer only ever stands on blocks it picked itself, which are valid.
+        let _ = self.flush_final_block();
     }
 }
 
-fn set_next_block_address(page: &mut PageAccessor, block_index: u8, next_block_address: BlockAddress) {
+fn set_next_block_address(page: &mut PageAccessor, block_index: u8, next_block_address: BlockAddress) -> Result<()> {
     let mut buffer = [0; BlockAddress::size_in_buffer()];
-    buffer.write_structure(&next_block_address);
-    page.set_block_data(block_index, &buffer, BLOCK_DATA_SIZE);
+    buffer.write_structure(&next_block_address)?;
+    page.set_block_data(block_index, &buffer, BLOCK_DATA_SIZE)
+}
+
+/// Lists the addresses of all blocks in the chain starting at `start_address`.
+pub fn block_chain(page_manager: &mut PageManager, start_address: BlockAddress) -> Result<Vec<BlockAddress>> {
+    let max_blocks = page_manager.page_count()? as usize * PAGE_BLOCK_COUNT;
+    let mut chain = Vec::new();
+    let mut address = start_address;
+    while address != BlockAddress::invalid() {
+        if chain.len() >= max_blocks {
+            return Err(Error::new(ErrorKind::InvalidData, "Block chain has a cycle"));
+        }
+
+        chain.push(address);
+        address = get_next_block_address(&page_manager.get_page(address.page_index)?, address.block_index)?;
+    }
+
+    Ok(chain)
+}
+
+/// Marks all blocks of the chain starting at `start_address` as free. Returns the number of freed blocks.
+pub fn free_chain(page_manager: &mut PageManager, start_address: BlockAddress) -> Result<usize> {
+    let chain = block_chain(page_manager, start_address)?;
+    for address in &chain {
+        let mut page = page_manager.get_page(address.page_index)?;
+        page.free_block(address.block_index)?;
+        page.commit()?;
+    }
+
+    Ok(chain.len())
 }
 
-fn get_next_block_address(page: &PageAccessor, block_index: u8) -> BlockAddress {
+pub fn get_next_block_address(page: &PageAccessor, block_index: u8) -> Result<BlockAddress> {
     page
-        .get_block_data(block_index, BLOCK_DATA_SIZE, BlockAddress::size_in_buffer())
+        .get_block_data(block_index, BLOCK_DATA_SIZE, BlockAddress::size_in_buffer())?
         .read_structure()
 }
\ No newline at end of file
@@ -0,0 +1,116 @@
+use std::{io::{Result, Error, ErrorKind}, net::SocketAddr, path::PathBuf};
+
+use axum::{Router, body::Bytes, extract::{Path, State}, http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response}, routing::{get, post}};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use serde_json::{Value, json};
+use tokio::net::TcpListener;
+
+use crate::{Histogram, worker::Worker};
+
+/// Serves the database at `path` on `address` until the server fails:
+///
+/// - `GET /keys/{key}`: the value, as raw bytes or, with `Accept: application/json`, as `{"key", "value"}`
+///   with a base64 value; 404 if there is no such key.
+/// - `PUT /keys/{key}`: stores the body, raw or, with `Content-Type: application/json`, the base64 `value` of
+///   a JSON object, replacing an existing value.
+/// - `DELETE /keys/{key}`: 404 if there is no such key.
+/// - `GET /stats`: record and page counts and size distributions as JSON.
+/// - `POST /compact`: defragments all records and frees unused blocks, see `defragment_all` and `gc`.
+///
+/// Keys may contain `/`; other reserved characters have to be percent-encoded.
+pub async fn serve(path: impl Into<PathBuf>, address: SocketAddr) -> Result<()> {
+    let router = Router::new()
+        .route("/keys/*key", get(get_record).put(put_record).delete(delete_record))
+        .route("/stats", get(stats))
+        .route("/compact", post(compact))
+        .with_state(Worker::start(path.into())?);
+    axum::serve(TcpListener::bind(address).await?, router).await
+}
+
+/// An error as a response: 400 for bad requests, 500 for everything else.
+struct RestError(Error);
+
+impl From<Error> for RestError {
+    fn from(error: Error) -> Self {
+        RestError(error)
+    }
+}
+
+impl IntoResponse for RestError {
+    fn into_response(self) -> Response {
+        let status = match self.0.kind() {
+            ErrorKind::InvalidInput => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status, self.0.to_string()).into_response()
+    }
+}
+
+type RestResult = std::result::Result<Response, RestError>;
+
+async fn get_record(State(worker): State<Worker>, Path(key): Path<String>, headers: HeaderMap) -> RestResult {
+    let record_key = key.clone();
+    let value = worker.run(move |db| {
+        let mut value = Vec::new();
+        Ok::<_, Error>(db.get_into(&record_key, &mut value)?.then_some(value))
+    }).await??;
+
+    let Some(value) = value else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    let response = if is_json(&headers, header::ACCEPT) {
+        axum::Json(json!({ "key": key, "value": BASE64.encode(value) })).into_response()
+    }
+    else {
+        ([(header::CONTENT_TYPE, "application/octet-stream")], value).into_response()
+    };
+    Ok(response)
+}
+
+async fn put_record(State(worker): State<Worker>, Path(key): Path<String>, headers: HeaderMap, body: Bytes)
+    -> RestResult {
+    let value = if is_json(&headers, header::CONTENT_TYPE) {
+        let invalid = || Error::new(ErrorKind::InvalidInput, "Expected {\"value\": \"<base64>\"}");
+        let body: Value = serde_json::from_slice(&body).map_err(|_| invalid())?;
+        let value = body.get("value").and_then(Value::as_str).ok_or_else(invalid)?;
+        BASE64.decode(value).map_err(|_| invalid())?
+    }
+    else {
+        body.to_vec()
+    };
+
+    worker.run(move |db| db.put(&key, &value)).await??;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+async fn delete_record(State(worker): State<Worker>, Path(key): Path<String>) -> RestResult {
+    let deleted = worker.run(move |db| db.delete(&key)).await??;
+    Ok(if deleted { StatusCode::NO_CONTENT } else { StatusCode::NOT_FOUND }.into_response())
+}
+
+async fn stats(State(worker): State<Worker>) -> RestResult {
+    let stats = worker.run(|db| db.stats()).await??;
+    let histogram = |h: &Histogram| json!({
+        "count": h.count(), "mean": h.mean(), "p50": h.percentile(50.0), "p99": h.percentile(99.0), "max": h.max(),
+    });
+    Ok(axum::Json(json!({
+        "record_count": stats.record_count,
+        "page_count": stats.page_count,
+        "free_blocks": stats.free_blocks,
+        "shared_value_records": stats.shared_value_records,
+        "key_sizes": histogram(&stats.key_sizes),
+        "value_sizes": histogram(&stats.value_sizes),
+        "blocks_per_record": histogram(&stats.blocks_per_record),
+    })).into_response())
+}
+
+async fn compact(State(worker): State<Worker>) -> RestResult {
+    let (defragmented_records, freed_bytes) = worker.run(|db| Ok::<_, Error>((db.defragment_all()?, db.gc()?))).await??;
+    Ok(axum::Json(json!({ "defragmented_records": defragmented_records, "freed_bytes": freed_bytes })).into_response())
+}
+
+fn is_json(headers: &HeaderMap, name: header::HeaderName) -> bool {
+    headers.get(name).and_then(|value| value.to_str().ok()).is_some_and(|value| value.contains("application/json"))
+}
@@ -0,0 +1,112 @@
+use std::fmt::{self, Display};
+
+/// Distribution of a size in power-of-two buckets: bucket 0 counts zeros and bucket `i` counts values
+/// in `2^(i-1)..2^i`.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct Histogram {
+    buckets: Vec<u64>,
+    count: u64,
+    sum: u64,
+    max: u64,
+}
+
+impl Histogram {
+    pub fn record(&mut self, value: u64) {
+        let bucket = (u64::BITS - value.leading_zeros()) as usize;
+        if self.buckets.len() <= bucket {
+            self.buckets.resize(bucket + 1, 0);
+        }
+
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum += value;
+        self.max = self.max.max(value);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 { 0.0 } else { self.sum as f64 / self.count as f64 }
+    }
+
+    /// Upper bound of the bucket holding the `percentile`th value (0 to 100), capped at the maximum.
+    pub fn percentile(&self, percentile: f64) -> u64 {
+        let rank = ((percentile / 100.0 * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return bucket_end(bucket).min(self.max);
+            }
+        }
+
+        self.max
+    }
+
+    /// Non-empty buckets as `(start, end, count)`, where the bucket holds values in `start..=end`.
+    pub fn buckets(&self) -> impl Iterator<Item = (u64, u64, u64)> + '_ {
+        self.buckets.iter().enumerate()
+            .filter(|(_, &count)| count > 0)
+            .map(|(bucket, &count)| (bucket_start(bucket), bucket_end(bucket), count))
+    }
+}
+
+fn bucket_start(bucket: usize) -> u64 {
+    if bucket == 0 { 0 } else { 1 << (bucket - 1) }
+}
+
+fn bucket_end(bucket: usize) -> u64 {
+    if bucket == 0 { 0 } else { u64::MAX >> (u64::BITS as usize - bucket) }
+}
+
+impl Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "count {}, mean {:.1}, p50 {}, p99 {}, max {}",
+            self.count, self.mean(), self.percentile(50.0), self.percentile(99.0), self.max)?;
+        for (start, end, count) in self.buckets() {
+            writeln!(f, "  {:>10} - {:<10} {}", start, end, count)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// What the records of a database look like, from one pass over the record chain.
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct Stats {
+    pub record_count: u64,
+    pub page_count: u64,
+    pub free_blocks: u64,
+    /// Records whose value is stored once for several records.
+    pub shared_value_records: u64,
+    pub key_sizes: Histogram,
+    pub value_sizes: Histogram,
+    /// Blocks of each record's own chain; a shared value counts as the reference to it.
+    pub blocks_per_record: Histogram,
+}
+
+/// Storage taken by the records under a key prefix, see `Database::estimate_size`.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct SizeEstimate {
+    pub record_count: u64,
+    /// Bytes of the blocks of the records' own chains; shared values aren't included.
+    pub bytes: u64,
+    /// Counted over all records rather than extrapolated from a sample of pages.
+    pub exact: bool,
+}
+
+/// What a maintenance operation would change, reported by its `_dry_run` variant without writing anything.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct DryRunReport {
+    /// Records that would be removed, or rewritten by `Database::defragment_all_dry_run`.
+    pub records: u64,
+    /// Bytes of the blocks that would be freed, or rewritten by `Database::defragment_all_dry_run`. Records are
+    /// counted by their own chains like in `SizeEstimate::bytes`.
+    pub bytes: u64,
+}
@@ -0,0 +1,54 @@
+use std::{io::{Result, Read, Write, Seek, Cursor}, fs::File, rc::Rc, cell::RefCell};
+
+/// Bytes a database lives in. Implemented for `File` and, for databases that never touch the disk,
+/// for `Cursor<Vec<u8>>`.
+pub trait Storage: Read + Write + Seek {
+    /// Current size in bytes.
+    fn size(&self) -> Result<u64>;
+    /// Truncates or extends the storage to `size` bytes.
+    fn set_size(&mut self, size: u64) -> Result<()>;
+    /// Makes the contents durable, see `SyncMode::Data`.
+    fn sync_data(&self) -> Result<()>;
+    /// Makes the contents and all metadata durable, see `SyncMode::All`.
+    fn sync_all(&self) -> Result<()>;
+}
+
+impl Storage for File {
+    fn size(&self) -> Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn set_size(&mut self, size: u64) -> Result<()> {
+        self.set_len(size)
+    }
+
+    fn sync_data(&self) -> Result<()> {
+        File::sync_data(self)
+    }
+
+    fn sync_all(&self) -> Result<()> {
+        File::sync_all(self)
+    }
+}
+
+impl Storage for Cursor<Vec<u8>> {
+    fn size(&self) -> Result<u64> {
+        Ok(self.get_ref().len() as u64)
+    }
+
+    fn set_size(&mut self, size: u64) -> Result<()> {
+        self.get_mut().resize(size as usize, 0);
+        Ok(())
+    }
+
+    fn sync_data(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn sync_all(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The storage of a database, shared by `Database` and `PageManager`.
+pub type SharedStorage = Rc<RefCell<Box<dyn Storage>>>;
@@ -0,0 +1,33 @@
+use std::{rc::Rc, time::Duration};
+
+use crate::determinism::Clock;
+
+/// Paces the I/O of a maintenance operation to a budget of bytes per second: whenever the bytes done run ahead
+/// of the time taken, sleeps until they are back within the budget.
+pub struct Throttle {
+    clock: Rc<dyn Clock>,
+    bytes_per_second: Option<u64>,
+    start_millis: u64,
+    bytes: u64,
+}
+
+impl Throttle {
+    /// A throttle starting now. `None` never sleeps.
+    pub fn new(clock: Rc<dyn Clock>, bytes_per_second: Option<u64>) -> Self {
+        let start_millis = clock.now_millis();
+        Throttle { clock, bytes_per_second, start_millis, bytes: 0 }
+    }
+
+    pub fn consume(&mut self, bytes: u64) {
+        let Some(bytes_per_second) = self.bytes_per_second else {
+            return;
+        };
+
+        self.bytes += bytes;
+        let due_millis = self.bytes.saturating_mul(1000) / bytes_per_second.max(1);
+        let elapsed_millis = self.clock.now_millis().saturating_sub(self.start_millis);
+        if due_millis > elapsed_millis {
+            self.clock.sleep(Duration::from_millis(due_millis - elapsed_millis));
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::{cell::RefCell, rc::Rc, time::{Duration, Instant}};
+
+/// Where the time of one operation went, see `Database::trace_next_op`. Phases nest: page loads during `find`
+/// count towards both `find` and `page_loads`.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct OpTrace {
+    pub total: Duration,
+    /// Walking the record chain to the record with the key.
+    pub find: Duration,
+    /// Pages read from the storage because they weren't cached.
+    pub page_loads: PhaseTime,
+    /// Changed pages written to the storage.
+    pub page_writes: PhaseTime,
+    /// Copying keys and values between records and the caller.
+    pub serialization: Duration,
+    /// `sync_data`/`sync_all` of the storage.
+    pub sync: Duration,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct PhaseTime {
+    pub count: u32,
+    pub time: Duration,
+}
+
+#[derive(Clone, Copy)]
+pub enum Phase {
+    Find,
+    PageLoad,
+    PageWrite,
+    Serialization,
+    Sync,
+}
+
+/// Collects an `OpTrace` while one is in progress. Shared by `Database` and `PageManager`.
+#[derive(Clone, Default)]
+pub struct Tracer {
+    trace: Rc<RefCell<Option<OpTrace>>>,
+}
+
+impl Tracer {
+    pub fn start(&self) {
+        *self.trace.borrow_mut() = Some(OpTrace::default());
+    }
+
+    pub fn finish(&self) -> OpTrace {
+        self.trace.borrow_mut().take().unwrap_or_default()
+    }
+
+    /// Measures `phase` until the returned span is dropped. Costs nothing while no trace is in progress.
+    pub fn span(&self, phase: Phase) -> Option<Span> {
+        self.trace.borrow().as_ref()?;
+        Some(Span { trace: self.trace.clone(), phase, start: Instant::now() })
+    }
+}
+
+pub struct Span {
+    trace: Rc<RefCell<Option<OpTrace>>>,
+    phase: Phase,
+    start: Instant,
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        let mut trace = self.trace.borrow_mut();
+        let Some(trace) = trace.as_mut() else {
+            return;
+        };
+
+        match self.phase {
+            Phase::Find => trace.find += elapsed,
+            Phase::PageLoad => add(&mut trace.page_loads, elapsed),
+            Phase::PageWrite => add(&mut trace.page_writes, elapsed),
+            Phase::Serialization => trace.serialization += elapsed,
+            Phase::Sync => trace.sync += elapsed,
+        }
+    }
+}
+
+fn add(phase: &mut PhaseTime, elapsed: Duration) {
+    phase.count += 1;
+    phase.time += elapsed;
+}
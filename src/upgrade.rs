@@ -0,0 +1,151 @@
+use std::{io::{Result, Read, Seek, SeekFrom, Error, ErrorKind}, ffi::OsString, fs::{self, File, OpenOptions},
+    ops::ControlFlow, path::Path, mem::size_of, str::from_utf8};
+
+use byteorder::{ByteOrder, NativeEndian};
+
+use crate::{Database, FORMAT_MAGIC, lock_exclusive, FORMAT_VERSION, paging::BlockAddress, Progress,
+    progress::report, utils::{ReadableWritable, ReadStructurePos}};
+
+// Layout of v1 files, which were written by transmuting `repr(Rust)` structures:
+// system info (first_record, last_record), pages header, then pages with the blocks first.
+const V1_FIRST_PAGE_OFFSET: u64 = 20;
+const V1_PAGE_SIZE: u64 = 4096;
+const V1_BLOCK_SIZE: usize = 64;
+const V1_BLOCK_DATA_SIZE: usize = V1_BLOCK_SIZE - 8;
+const V1_PAGE_BLOCK_COUNT: u8 = 63;
+const V1_RECORD_HEADER_SIZE: usize = 16;
+
+// Format versions 2 and 3 were only written by development builds, and their record header and page layouts
+// changed between builds without a version bump, so a file's version doesn't say how to read it.
+const DEVELOPMENT_FORMAT_VERSIONS: [u32; 2] = [2, 3];
+
+#[derive(Clone)]
+#[repr(C)]
+struct FormatSignature {
+    magic: [u8; 4],
+    format_version: u32,
+}
+
+impl ReadableWritable for FormatSignature {
+    fn read_to_buffer(read_action: impl FnOnce(&mut [u8]) -> Result<Self>) -> Result<Self> {
+        let mut buffer = [0; size_of::<Self>()];
+        read_action(&mut buffer)
+    }
+}
+
+/// Converts the file at `path` to the current format. The original is only replaced once the converted copy
+/// is complete, so stopping through `progress` or a crash leaves it as it was.
+pub fn upgrade_in_place(path: &Path, progress: &mut impl FnMut(Progress) -> ControlFlow<()>) -> Result<bool> {
+    let mut file = OpenOptions::new().read(true).open(path)?;
+    lock_exclusive(&file)?;
+    let file_len = file.metadata()?.len();
+    if file_len == 0 {
+        return Ok(false);
+    }
+
+    if file_len < FormatSignature::size_in_buffer() as u64 {
+        return Err(Error::new(ErrorKind::InvalidData, "File is too small to be a database"));
+    }
+
+    let signature: FormatSignature = file.read_structure_from_pos(0)?;
+    if signature.magic == FORMAT_MAGIC {
+        return match signature.format_version {
+            FORMAT_VERSION => Ok(false),
+            version if DEVELOPMENT_FORMAT_VERSIONS.contains(&version) => Err(Error::new(ErrorKind::InvalidData,
+                format!("Format version {:?} was written by a development build and can't be upgraded, export the records \
+                    with that build and import them", version))),
+            version => Err(Error::new(ErrorKind::InvalidData, format!("Unknown format version {:?}", version))),
+        };
+    }
+
+    let upgraded_path = upgraded_path(path);
+    if Path::new(&upgraded_path).exists() {
+        fs::remove_file(&upgraded_path)?;
+    }
+
+    let result = Database::new(&upgraded_path).and_then(|mut upgraded_db| {
+        copy_v1_records(&mut file, &mut upgraded_db, progress)?;
+        upgraded_db.write_system_info()?;
+        upgraded_db.file.borrow().sync_all()
+    });
+    if let Err(error) = result {
+        let _ = fs::remove_file(&upgraded_path);
+        return Err(error);
+    }
+
+    drop(file);
+    fs::rename(&upgraded_path, path)?;
+    Ok(true)
+}
+
+fn upgraded_path(path: &Path) -> OsString {
+    let mut upgraded_path = path.as_os_str().to_owned();
+    upgraded_path.push(".upgrade");
+    upgraded_path
+}
+
+fn copy_v1_records(file: &mut File, target: &mut Database, progress: &mut impl FnMut(Progress) -> ControlFlow<()>)
+    -> Result<()> {
+    let mut copied_records = 0;
+    let mut system_info = [0; 8];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut system_info)?;
+
+    let mut record_address = read_v1_address(&system_info);
+    while record_address != BlockAddress::invalid() {
+        let record = read_v1_record(file, record_address)?;
+        let header = &record[..V1_RECORD_HEADER_SIZE];
+        let key_size = NativeEndian::read_i32(&header[8..12]) as usize;
+
+        let key = from_utf8(&record[V1_RECORD_HEADER_SIZE..V1_RECORD_HEADER_SIZE + key_size])
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        target.set(key, &record[V1_RECORD_HEADER_SIZE + key_size..])?;
+        copied_records += 1;
+        report(progress, copied_records, None)?;
+
+        record_address = read_v1_address(header);
+    }
+
+    Ok(())
+}
+
+/// Collects the header, key and data of a v1 record by following its block chain.
+fn read_v1_record(file: &mut File, start_address: BlockAddress) -> Result<Vec<u8>> {
+    let mut record = Vec::new();
+    let mut record_size = None;
+    let mut block = [0; V1_BLOCK_SIZE];
+    let mut block_address = start_address;
+
+    loop {
+        if block_address == BlockAddress::invalid() {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "Record chain ends before the record data"));
+        }
+
+        if block_address.page_index < 0 || block_address.block_index >= V1_PAGE_BLOCK_COUNT {
+            return Err(Error::new(ErrorKind::InvalidData, format!("Invalid block address {}", block_address)));
+        }
+
+        let block_position = V1_FIRST_PAGE_OFFSET + block_address.page_index as u64 * V1_PAGE_SIZE
+            + (block_address.block_index as usize * V1_BLOCK_SIZE) as u64;
+        file.seek(SeekFrom::Start(block_position))?;
+        file.read_exact(&mut block)?;
+        record.extend_from_slice(&block[..V1_BLOCK_DATA_SIZE]);
+
+        let size = *record_size.get_or_insert_with(|| {
+            let key_size = NativeEndian::read_i32(&record[8..12]).max(0) as usize;
+            let data_size = NativeEndian::read_i32(&record[12..16]).max(0) as usize;
+            V1_RECORD_HEADER_SIZE + key_size + data_size
+        });
+
+        if record.len() >= size {
+            record.truncate(size);
+            return Ok(record);
+        }
+
+        block_address = read_v1_address(&block[V1_BLOCK_DATA_SIZE..]);
+    }
+}
+
+fn read_v1_address(bytes: &[u8]) -> BlockAddress {
+    BlockAddress::new(NativeEndian::read_i32(&bytes[0..4]), bytes[4])
+}
@@ -1,4 +1,4 @@
-use std::{io::{Read, Write, Seek, Result, SeekFrom, Cursor}, mem::{size_of}, slice};
+use std::{io::{Read, Write, Seek, Result, SeekFrom, Cursor, Error, ErrorKind}, mem::{size_of}, slice};
 
 pub trait ReadableWritable : Sized + Clone {
     fn size_in_buffer() -> usize {
@@ -8,19 +8,24 @@ pub trait ReadableWritable : Sized + Clone {
     fn read(reader: &mut impl Read) -> Result<Self> {
         Self::read_to_buffer(|buffer| {
             reader.read_exact(buffer)?;
-            unsafe { Ok(buffer.as_ptr().cast::<Self>().as_ref().unwrap().clone()) }
+            // The buffer is only aligned for bytes, while the structures may hold `u64` fields.
+            unsafe { Ok(buffer.as_ptr().cast::<Self>().read_unaligned()) }
         })
     }
 
     fn write(&self, writer: &mut impl Write) -> Result<()> {
-        let slice = unsafe { slice::from_raw_parts((self as *const Self) as *const u8, size_of::<Self>()) };
-        writer.write_all(slice)?;
+        writer.write_all(struct_bytes(self))?;
         Ok(())
     }
 
     fn read_to_buffer(read_action: impl FnOnce(&mut [u8]) -> Result<Self>) -> Result<Self>;
 }
 
+/// The in-memory representation of `structure`, which is what gets stored.
+pub fn struct_bytes<T: ReadableWritable>(structure: &T) -> &[u8] {
+    unsafe { slice::from_raw_parts((structure as *const T) as *const u8, size_of::<T>()) }
+}
+
 pub trait ReadStructure : Read + Sized {
     fn read_structure<T: ReadableWritable>(&mut self) -> Result<T> {
         T::read(self)
@@ -56,27 +61,29 @@ impl<W: Write + Sized> WriteStructure for W {}
 impl<W: Write + Seek + Sized> WriteStructurePos for W {}
 
 pub trait ArrayStructReaderWriter {
-    fn read_structure<T: ReadableWritable>(&self) -> T;
+    fn read_structure<T: ReadableWritable>(&self) -> Result<T>;
 
-    fn write_structure<T: ReadableWritable>(&mut self, structure: &T);
+    fn write_structure<T: ReadableWritable>(&mut self, structure: &T) -> Result<()>;
 }
 
 impl ArrayStructReaderWriter for [u8] {
-    fn read_structure<T: ReadableWritable>(&self) -> T {
-        if self.len() < T::size_in_buffer() {
-            panic!("Buffer can't be less than structure size");
-        }
-
+    fn read_structure<T: ReadableWritable>(&self) -> Result<T> {
+        check_buffer_size::<T>(self)?;
         let mut cursor = Cursor::new(self);
-        T::read(&mut cursor).unwrap()
+        T::read(&mut cursor)
     }
 
-    fn write_structure<T: ReadableWritable>(&mut self, structure: &T) {
-        if self.len() < T::size_in_buffer() {
-            panic!("Buffer can't be less than structure size");
-        }
-
+    fn write_structure<T: ReadableWritable>(&mut self, structure: &T) -> Result<()> {
+        check_buffer_size::<T>(self)?;
         let mut cursor = Cursor::new(self);
-        structure.write(&mut cursor).unwrap();
+        structure.write(&mut cursor)
     }
+}
+
+fn check_buffer_size<T: ReadableWritable>(buffer: &[u8]) -> Result<()> {
+    if buffer.len() < T::size_in_buffer() {
+        return Err(Error::new(ErrorKind::InvalidInput, "Buffer can't be less than structure size"));
+    }
+
+    Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,48 @@
+use std::{io::{Result, Error}, path::PathBuf, sync::mpsc, thread};
+
+use tokio::sync::oneshot;
+
+use crate::Database;
+
+type Job = Box<dyn FnOnce(&mut Database) + Send>;
+
+/// Owns a `Database` on a thread of its own, since `Database` isn't `Send`, and runs the operations of async
+/// servers on it one at a time.
+#[derive(Clone)]
+pub(crate) struct Worker {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl Worker {
+    /// Opens the database at `path` on the new thread. The thread ends once every clone of the worker is dropped.
+    pub fn start(path: PathBuf) -> Result<Self> {
+        let (jobs, receiver) = mpsc::channel::<Job>();
+        let (opened, open_result) = mpsc::channel();
+        thread::spawn(move || {
+            let mut db = match Database::new(&path) {
+                Ok(db) => {
+                    let _ = opened.send(Ok(()));
+                    db
+                },
+                Err(error) => {
+                    let _ = opened.send(Err(error));
+                    return;
+                },
+            };
+
+            for job in receiver {
+                job(&mut db);
+            }
+        });
+
+        open_result.recv().map_err(Error::other)??;
+        Ok(Worker { jobs })
+    }
+
+    pub async fn run<T: Send + 'static>(&self, op: impl FnOnce(&mut Database) -> T + Send + 'static) -> Result<T> {
+        let (sender, receiver) = oneshot::channel();
+        self.jobs.send(Box::new(move |db| { let _ = sender.send(op(db)); }))
+            .map_err(|_| Error::other("The database thread has stopped"))?;
+        receiver.await.map_err(|_| Error::other("The database thread has stopped"))
+    }
+}